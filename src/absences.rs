@@ -0,0 +1,102 @@
+use std::collections::HashSet;
+
+use crate::config::CONFIG;
+use crate::diff::ItemDiff;
+use crate::history;
+use crate::scraper::{ShopItemId, ShopItems};
+use crate::storage::CDN_CACHE_DB;
+use color_eyre::Result;
+use log::{debug, info};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use sled::Tree;
+
+const ABSENCES_TREE: &str = "absences";
+
+// an item that was in the last snapshot but hasn't shown up since
+#[derive(Serialize, Deserialize)]
+struct Absence {
+    missing_since: i64,
+    runs: u32,
+}
+
+static ABSENCES: Lazy<Tree> = Lazy::new(|| CDN_CACHE_DB.open_tree(ABSENCES_TREE).unwrap());
+
+fn absence_key(id: ShopItemId) -> [u8; 8] {
+    (id as u64).to_be_bytes()
+}
+
+fn removal_confirmed(absence: &Absence, now: i64) -> bool {
+    let missing_mins = (now - absence.missing_since) / 60;
+    absence.runs >= CONFIG.removal_confirm_runs
+        || (CONFIG.removal_confirm_mins > 0 && missing_mins >= CONFIG.removal_confirm_mins)
+}
+
+// items often drop off the shop page for a single scrape, so anything that's only just gone
+// missing is kept in `items` until it's been gone for long enough to call it removed - returns
+// the items whose removal is confirmed
+pub fn debounce_removals(items: &mut ShopItems, old_items: &ShopItems) -> Result<Vec<ShopItemId>> {
+    let now = time_format::now()?;
+    let present: HashSet<ShopItemId> = items.iter().map(|item| item.id).collect();
+    let old_ids: HashSet<ShopItemId> = old_items.iter().map(|item| item.id).collect();
+
+    for key in ABSENCES.iter().keys() {
+        let key = key?;
+        let id = u64::from_be_bytes(key.as_ref().try_into()?) as ShopItemId;
+        if present.contains(&id) {
+            debug!("Item {id} is back after going missing");
+            ABSENCES.remove(key)?;
+        } else if !old_ids.contains(&id) {
+            // a confirmed removal is only done with once a snapshot without the item has been
+            // written, until then (e.g. while the diff is held back) it stays confirmed
+            debug!("Item {id}'s removal has made it into the snapshot");
+            ABSENCES.remove(key)?;
+        }
+    }
+
+    let mut confirmed = Vec::new();
+    for old_item in old_items.iter().filter(|item| !present.contains(&item.id)) {
+        let key = absence_key(old_item.id);
+        let mut absence = match ABSENCES.get(key)? {
+            Some(value) => serde_json::from_slice(&value)?,
+            None => Absence {
+                missing_since: now,
+                runs: 0,
+            },
+        };
+        absence.runs += 1;
+
+        if removal_confirmed(&absence, now) {
+            info!(
+                "Item {} has been missing for {} runs, treating it as removed",
+                old_item.id, absence.runs
+            );
+            confirmed.push(old_item.id);
+        } else {
+            info!(
+                "Item {} is missing ({} run(s) so far), keeping it for now",
+                old_item.id, absence.runs
+            );
+            items.push(old_item.clone());
+        }
+        ABSENCES.insert(key, serde_json::to_vec(&absence)?)?;
+    }
+
+    items.sort_by_key(|item| item.id);
+    ABSENCES.flush()?;
+    Ok(confirmed)
+}
+
+// "new" items we've tracked prices for before are really back in the shop
+pub fn label_returning(diff: &mut ItemDiff) -> Result<()> {
+    let mut new_items = Vec::new();
+    for item in std::mem::take(&mut diff.new_items) {
+        if history::seen_before(item.id)? {
+            diff.returning_items.push(item);
+        } else {
+            new_items.push(item);
+        }
+    }
+    diff.new_items = new_items;
+    Ok(())
+}
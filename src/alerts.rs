@@ -0,0 +1,140 @@
+use std::collections::BTreeMap;
+
+use crate::config::CONFIG;
+use crate::metrics::ADMIN_ALERT_FAILURES;
+use crate::notifier::build_notifier;
+use crate::scraper::{Region, ScrapeError};
+use crate::storage::CDN_CACHE_DB;
+use color_eyre::Result;
+use log::{error, info, warn};
+use once_cell::sync::Lazy;
+use sled::Tree;
+
+const ALERT_STATE_TREE: &str = "alerts";
+const SESSION_EXPIRED_KEY: &str = "session-expired";
+const REGIONS_KEY: &str = "regions";
+const REGION_DROPDOWN_MISSING_KEY: &str = "region-dropdown-missing";
+
+// remembers which alerts are currently raised, so they're sent once rather than every run
+static ALERT_STATE: Lazy<Tree> = Lazy::new(|| CDN_CACHE_DB.open_tree(ALERT_STATE_TREE).unwrap());
+
+// a broken admin notifier mustn't take the scrape down with it, so failures are only logged and
+// counted - returns whether the alert went out, for alerts that should be tried again next run
+pub fn send_admin_alert(message: &str) -> bool {
+    let Some(destination) = &CONFIG.admin_notifier else {
+        warn!("No ADMIN_NOTIFIER configured, not sending alert: {message}");
+        return true;
+    };
+    match build_notifier(destination).alert(message) {
+        Ok(()) => {
+            info!("Sent admin alert: {message}");
+            true
+        }
+        Err(err) => {
+            ADMIN_ALERT_FAILURES.inc();
+            error!("Failed to send admin alert ({message}): {err:?}");
+            false
+        }
+    }
+}
+
+pub fn session_expired(err: &ScrapeError) -> Result<()> {
+    if ALERT_STATE.contains_key(SESSION_EXPIRED_KEY)? {
+        return Ok(());
+    }
+
+    if !send_admin_alert(&format!(
+        "Flavortown tracker can't scrape the shop: the COOKIE has expired ({err}). \
+         Every scrape will fail until you update COOKIE and restart the tracker."
+    )) {
+        return Ok(());
+    }
+    ALERT_STATE.insert(SESSION_EXPIRED_KEY, &[])?;
+    ALERT_STATE.flush()?;
+    Ok(())
+}
+
+pub fn session_ok() -> Result<()> {
+    if ALERT_STATE.remove(SESSION_EXPIRED_KEY)?.is_some() {
+        ALERT_STATE.flush()?;
+        send_admin_alert("Flavortown tracker resumed: the session cookie is working again.");
+    }
+    Ok(())
+}
+
+// the region dropdown's markup changing would quietly stop us noticing new regions
+pub fn region_dropdown_missing() -> Result<()> {
+    if ALERT_STATE.contains_key(REGION_DROPDOWN_MISSING_KEY)? {
+        return Ok(());
+    }
+
+    if !send_admin_alert(
+        "Flavortown tracker couldn't read the shop's region dropdown, so it's scraping the regions it already knew about. \
+         New regions won't be picked up until the parser is updated.",
+    ) {
+        return Ok(());
+    }
+    ALERT_STATE.insert(REGION_DROPDOWN_MISSING_KEY, &[])?;
+    ALERT_STATE.flush()?;
+    Ok(())
+}
+
+// the regions (and their names) from the last time we could read the dropdown, which outlive
+// a restart unlike `Region::all()`
+pub fn last_seen_regions() -> Result<Vec<(Region, String)>> {
+    let Some(value) = ALERT_STATE.get(REGIONS_KEY)? else {
+        return Ok(Vec::new());
+    };
+    let regions: BTreeMap<String, String> = serde_json::from_slice(&value)?;
+    Ok(regions
+        .into_iter()
+        .map(|(code, name)| (Region::new(&code), name))
+        .collect())
+}
+
+// tells the operator when flavortown adds, removes or renames a region
+pub fn regions_seen(regions: &[(Region, String)]) -> Result<()> {
+    if ALERT_STATE.remove(REGION_DROPDOWN_MISSING_KEY)?.is_some() {
+        ALERT_STATE.flush()?;
+        send_admin_alert("Flavortown tracker can read the shop's region dropdown again.");
+    }
+
+    let current: BTreeMap<String, String> = regions
+        .iter()
+        .map(|(region, name)| (region.code().to_string(), name.clone()))
+        .collect();
+    let previous: Option<BTreeMap<String, String>> = match ALERT_STATE.get(REGIONS_KEY)? {
+        Some(value) => Some(serde_json::from_slice(&value)?),
+        None => None,
+    };
+    if previous.as_ref() == Some(&current) {
+        return Ok(());
+    }
+
+    // the first scrape has nothing to compare against
+    if let Some(previous) = previous {
+        let mut changes = Vec::new();
+        for (code, name) in &current {
+            match previous.get(code) {
+                None => changes.push(format!("{name} ({code}) added")),
+                Some(old_name) if old_name != name => {
+                    changes.push(format!("{old_name} ({code}) renamed to {name}"));
+                }
+                Some(_) => {}
+            }
+        }
+        for (code, name) in &previous {
+            if !current.contains_key(code) {
+                changes.push(format!("{name} ({code}) removed"));
+            }
+        }
+        send_admin_alert(&format!(
+            "Flavortown's shop regions changed: {}",
+            changes.join(", ")
+        ));
+    }
+
+    ALERT_STATE.insert(REGIONS_KEY, serde_json::to_vec(&current)?)?;
+    ALERT_STATE.flush()?;
+    Ok(())
+}
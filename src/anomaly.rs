@@ -0,0 +1,171 @@
+use crate::alerts::send_admin_alert;
+use crate::config::CONFIG;
+use std::collections::BTreeSet;
+
+use crate::diff::ItemDiff;
+use crate::scraper::ShopItemId;
+use crate::storage::CDN_CACHE_DB;
+use color_eyre::Result;
+use log::{info, warn};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use sled::Tree;
+
+const ANOMALY_TREE: &str = "anomaly";
+const HELD_DIFF_KEY: &str = "held-diff";
+
+// a suspiciously large diff we're waiting to see again before believing it - tracked by which
+// items it touches rather than the whole diff, since prices and stock keep moving in the meantime
+#[derive(Serialize, Deserialize)]
+struct HeldDiff {
+    #[serde(default)]
+    changed_ids: BTreeSet<ShopItemId>,
+    runs: u32,
+}
+
+// ids of every item that vanished or changed, leaving out the ones another guard has already
+// confirmed
+fn changed_ids(diff: &ItemDiff, confirmed: &BTreeSet<ShopItemId>) -> BTreeSet<ShopItemId> {
+    diff.deleted_items
+        .iter()
+        .chain(diff.updated_items.iter().map(|(_, new)| new))
+        .map(|item| item.id)
+        .filter(|id| !confirmed.contains(id))
+        .collect()
+}
+
+// the held diff is still there if everything it touched still is, whatever else changed on top
+fn still_held(held: &HeldDiff, changed_ids: &BTreeSet<ShopItemId>) -> bool {
+    !held.changed_ids.is_empty() && held.changed_ids.is_subset(changed_ids)
+}
+
+static ANOMALY_STATE: Lazy<Tree> = Lazy::new(|| CDN_CACHE_DB.open_tree(ANOMALY_TREE).unwrap());
+
+fn is_anomalous(changed: usize, old_item_count: usize, anomaly_percent: u32) -> bool {
+    old_item_count > 0 && changed * 100 > old_item_count * anomaly_percent as usize
+}
+
+fn release(message: &str) -> Result<()> {
+    if ANOMALY_STATE.remove(HELD_DIFF_KEY)?.is_some() {
+        ANOMALY_STATE.flush()?;
+        send_admin_alert(message);
+    }
+    Ok(())
+}
+
+// whether to sit on this diff for now: a truncated or maintenance page looks like most of the
+// shop vanishing at once, so big diffs have to show up on several runs in a row before we send them
+pub fn hold_back(
+    diff: &ItemDiff,
+    old_item_count: usize,
+    confirmed: &BTreeSet<ShopItemId>,
+) -> Result<bool> {
+    let changed_ids = changed_ids(diff, confirmed);
+    let changed = changed_ids.len();
+    if !is_anomalous(changed, old_item_count, CONFIG.anomaly_percent) {
+        release("The suspicious shop update went away on its own, nothing was sent.")?;
+        return Ok(false);
+    }
+
+    let held: Option<HeldDiff> = match ANOMALY_STATE.get(HELD_DIFF_KEY)? {
+        Some(value) => Some(serde_json::from_slice(&value)?),
+        None => None,
+    };
+    let runs = match &held {
+        Some(held) if still_held(held, &changed_ids) => held.runs + 1,
+        _ => 1,
+    };
+
+    if runs >= CONFIG.anomaly_confirm_runs {
+        info!("Large diff has held for {runs} runs, sending it");
+        release(&format!(
+            "The suspicious shop update was still there after {runs} runs, so it's been sent."
+        ))?;
+        return Ok(false);
+    }
+
+    warn!(
+        "{changed} of {old_item_count} items vanished or changed at once, holding the diff back ({runs}/{} runs)",
+        CONFIG.anomaly_confirm_runs
+    );
+    if held.is_none() {
+        send_admin_alert(&format!(
+            "Flavortown tracker is holding back a suspicious shop update: {changed} of {old_item_count} items vanished or changed at once. \
+             It'll only be sent if it's still there after {} runs.",
+            CONFIG.anomaly_confirm_runs
+        ));
+    }
+
+    ANOMALY_STATE.insert(
+        HELD_DIFF_KEY,
+        serde_json::to_vec(&HeldDiff { changed_ids, runs })?,
+    )?;
+    ANOMALY_STATE.flush()?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scraper::{ShopItem, Stock};
+
+    fn item(id: ShopItemId, stock: Stock) -> ShopItem {
+        ShopItem {
+            title: format!("Item {id}"),
+            description: String::new(),
+            prices: std::collections::HashMap::new(),
+            image_url: "https://flavortown.hackclub.com/item.png".parse().unwrap(),
+            image_sha256: None,
+            stock,
+            image_id: id,
+            id,
+        }
+    }
+
+    fn mass_removal(stock_of_item_9: u32) -> ItemDiff {
+        ItemDiff {
+            new_items: Vec::new(),
+            deleted_items: (1..=8).map(|id| item(id, Stock::Unlimited)).collect(),
+            updated_items: vec![(
+                item(9, Stock::Limited(10)),
+                item(9, Stock::Limited(stock_of_item_9)),
+            )],
+            returning_items: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn confirms_while_stock_keeps_changing() {
+        let mut held = HeldDiff {
+            changed_ids: changed_ids(&mass_removal(9), &BTreeSet::new()),
+            runs: 1,
+        };
+        for stock in [8, 7] {
+            let diff = mass_removal(stock);
+            assert_ne!(diff, mass_removal(9));
+            assert!(still_held(&held, &changed_ids(&diff, &BTreeSet::new())));
+            held.runs += 1;
+        }
+        assert_eq!(held.runs, 3);
+
+        // the removed items coming back means it was a blip after all
+        let mut recovered = mass_removal(7);
+        recovered.deleted_items.truncate(2);
+        assert!(!still_held(
+            &held,
+            &changed_ids(&recovered, &BTreeSet::new())
+        ));
+    }
+
+    #[test]
+    fn skips_removals_that_are_already_confirmed() {
+        let diff = mass_removal(9);
+        let changed = changed_ids(&diff, &BTreeSet::new()).len();
+        assert!(is_anomalous(changed, 10, 50));
+
+        // the removal debounce has already waited for items 1-8 to stay gone
+        let confirmed = (1..=8).collect();
+        assert_eq!(changed_ids(&diff, &confirmed), BTreeSet::from([9]));
+        assert!(!is_anomalous(changed_ids(&diff, &confirmed).len(), 10, 50));
+    }
+}
@@ -0,0 +1,171 @@
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::PathBuf;
+
+use crate::config::CONFIG;
+use crate::http;
+use crate::mirror::MIRROR_CLIENT;
+use crate::storage::CDN_CACHE_DB;
+use color_eyre::{Result, eyre::eyre};
+use log::{debug, info};
+use once_cell::sync::Lazy;
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sled::Tree;
+
+const IMAGE_HASHES_TREE: &str = "image-hashes";
+const ARCHIVE_DIR: &str = "image-archive";
+
+// every image we've ever downloaded, stored by content so snapshots can still show it
+// after flavortown or the mirror has deleted it
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ArchivedImage {
+    pub sha256: String,
+    pub ext: String,
+    // the activestorage url it was downloaded from, to fetch it again if the file goes missing
+    #[serde(default)]
+    pub source_url: Option<Url>,
+}
+
+impl ArchivedImage {
+    pub fn file_name(&self) -> String {
+        format!("{}.{}", self.sha256, self.ext)
+    }
+
+    // fanned out by the first two hex digits, like git objects
+    pub fn path(&self) -> PathBuf {
+        CONFIG
+            .storage_path
+            .join(ARCHIVE_DIR)
+            .join(&self.sha256[..2])
+            .join(self.file_name())
+    }
+
+    pub fn read(&self) -> Result<Vec<u8>> {
+        Ok(fs::read(self.path())?)
+    }
+
+    fn store(&self, file: &[u8]) -> Result<()> {
+        let path = self.path();
+        if path.exists() {
+            return Ok(());
+        }
+        debug!("Archiving image as {}", self.file_name());
+        fs::create_dir_all(path.parent().unwrap())?;
+        // write-then-rename, so a crash can't leave a truncated image behind
+        let tmp_path = path.with_extension(format!("{}.tmp", fastrand::u64(..)));
+        fs::write(&tmp_path, file)?;
+        fs::rename(&tmp_path, &path)?;
+        Ok(())
+    }
+
+    // the archived file, downloading it again if it's been lost
+    pub fn read_or_refetch(&self) -> Result<Vec<u8>> {
+        if let Ok(file) = self.read() {
+            return Ok(file);
+        }
+        let source_url = self
+            .source_url
+            .as_ref()
+            .ok_or_else(|| eyre!("{} is missing and has no source url", self.file_name()))?;
+        let file = download(source_url)?;
+        if hex::encode(Sha256::digest(&file)) != self.sha256 {
+            return Err(eyre!(
+                "{source_url} no longer matches archived image {}",
+                self.sha256
+            ));
+        }
+        self.store(&file)?;
+        Ok(file)
+    }
+}
+
+// image_id -> the archived image's hash
+static IMAGE_HASHES: Lazy<Tree> = Lazy::new(|| CDN_CACHE_DB.open_tree(IMAGE_HASHES_TREE).unwrap());
+
+pub fn archived_image(image_id: usize) -> Result<Option<ArchivedImage>> {
+    match IMAGE_HASHES.get(image_id.to_le_bytes())? {
+        Some(value) => Ok(Some(serde_json::from_slice(&value)?)),
+        None => Ok(None),
+    }
+}
+
+fn ext_from_url(url: &Url) -> Option<String> {
+    let filename = url.path_segments()?.next_back()?;
+
+    std::path::Path::new(filename)
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|s| s.to_string())
+}
+
+// any image_id's entry for this content - they're all the same picture
+pub fn find_by_hash(sha256: &str) -> Result<Option<ArchivedImage>> {
+    for value in IMAGE_HASHES.iter().values() {
+        let archived: ArchivedImage = serde_json::from_slice(&value?)?;
+        if archived.sha256 == sha256 {
+            return Ok(Some(archived));
+        }
+    }
+    Ok(None)
+}
+
+// activestorage image urls usually redirect to the blob itself, which the scraping client
+// won't follow - so this goes through the cookie-less client, and anything but a 2xx at the end
+// of the redirects is an error rather than something to hash
+fn download(url: &Url) -> Result<Vec<u8>> {
+    let res = http::send(MIRROR_CLIENT.get(url.clone()))?;
+    if !res.status().is_success() {
+        return Err(eyre!("downloading {url} returned {}", res.status()));
+    }
+    Ok(res.bytes()?.to_vec())
+}
+
+fn store_download(image_id: usize, image_url: &Url, file: &[u8]) -> Result<ArchivedImage> {
+    let ext = ext_from_url(image_url).ok_or_else(|| {
+        eyre!("when trying to archive {image_url}, I couldn't get the file extension")
+    })?;
+    let archived = ArchivedImage {
+        sha256: hex::encode(Sha256::digest(file)),
+        ext,
+        source_url: Some(image_url.clone()),
+    };
+    archived.store(file)?;
+
+    IMAGE_HASHES.insert(image_id.to_le_bytes(), serde_json::to_vec(&archived)?)?;
+    Ok(archived)
+}
+
+pub fn archive_image(image_id: usize, image_url: &Url) -> Result<ArchivedImage> {
+    store_download(image_id, image_url, &download(image_url)?)
+}
+
+// hashes are cached by image_id, so a blob whose bytes get swapped out would keep its old hash
+// forever - this downloads the given images again and re-archives the ones that changed, which
+// the next scrape then reports as an image change. returns how many had
+pub fn recheck_sources(image_ids: &BTreeSet<usize>) -> Result<usize> {
+    let mut changed = 0;
+    for &image_id in image_ids {
+        let Some(archived) = archived_image(image_id)? else {
+            continue;
+        };
+        let Some(source_url) = &archived.source_url else {
+            continue;
+        };
+        // blobs get deleted along with their items, which is what the archive is for
+        let file = match download(source_url) {
+            Ok(file) => file,
+            Err(err) => {
+                debug!("Couldn't recheck image {image_id}: {err}");
+                continue;
+            }
+        };
+        if hex::encode(Sha256::digest(&file)) != archived.sha256 {
+            info!("Image {image_id} changed at {source_url}, archiving the new version");
+            store_download(image_id, source_url, &file)?;
+            changed += 1;
+        }
+    }
+    Ok(changed)
+}
@@ -0,0 +1,159 @@
+use std::collections::BTreeSet;
+
+use crate::archive::{ArchivedImage, archived_image, find_by_hash, recheck_sources};
+use crate::metrics::CDN_VERIFY_CHECKS;
+use crate::mirror::MIRROR_CLIENT;
+use crate::storage::{
+    CDN_CACHE_DB, MIRROR_URLS, forget_legacy_cdn_url, image_mirror, load_latest_snapshot,
+};
+use color_eyre::{Result, eyre::eyre};
+use log::{info, warn};
+use once_cell::sync::Lazy;
+use reqwest::{StatusCode, Url};
+use sled::Tree;
+
+const CDN_VERIFY_TREE: &str = "cdn-verify";
+const LAST_VERIFY_KEY: &str = "last-verify";
+
+// when the daemon last checked the mirror, so restarts don't keep pushing the next check back
+static VERIFY_STATE: Lazy<Tree> = Lazy::new(|| CDN_CACHE_DB.open_tree(CDN_VERIFY_TREE).unwrap());
+
+#[derive(Default, Debug)]
+pub struct VerifyStats {
+    pub checked: usize,
+    pub ok: usize,
+    pub reuploaded: usize,
+    pub failed: usize,
+    // images whose bytes changed at flavortown without the url changing
+    pub changed_at_source: usize,
+}
+
+impl std::fmt::Display for VerifyStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "checked {} mirrored images: {} ok, {} re-uploaded, {} failed ({} changed at the source)",
+            self.checked, self.ok, self.reuploaded, self.failed, self.changed_at_source
+        )
+    }
+}
+
+enum UrlState {
+    Ok,
+    Gone,
+    // the mirror is down or misbehaving, which doesn't mean the image is gone
+    Unknown(String),
+}
+
+fn check_url(url: &Url) -> UrlState {
+    match MIRROR_CLIENT.head(url.clone()).send() {
+        Ok(res) if res.status().is_success() => UrlState::Ok,
+        Ok(res) if matches!(res.status(), StatusCode::NOT_FOUND | StatusCode::GONE) => {
+            UrlState::Gone
+        }
+        Ok(res) => UrlState::Unknown(format!("HEAD returned {}", res.status())),
+        Err(err) => UrlState::Unknown(err.to_string()),
+    }
+}
+
+fn reupload(archived: &ArchivedImage) -> Result<Url> {
+    let mirror = image_mirror()?.ok_or_else(|| eyre!("no image mirror is configured"))?;
+    let url = mirror.upload(&archived.file_name(), archived.read_or_refetch()?)?;
+    MIRROR_URLS.insert(&archived.sha256, url.as_str().as_bytes())?;
+    Ok(url)
+}
+
+// only images still in the shop are worth downloading again - the rest are archived for good
+fn current_image_ids() -> Result<BTreeSet<usize>> {
+    Ok(load_latest_snapshot()?
+        .unwrap_or_default()
+        .iter()
+        .map(|item| item.image_id)
+        .collect())
+}
+
+// re-hashes the images in the latest snapshot at their source, then HEAD-checks every mirrored image and
+// re-uploads the ones the mirror has lost
+pub fn verify() -> Result<VerifyStats> {
+    let mut stats = VerifyStats {
+        changed_at_source: recheck_sources(&current_image_ids()?)?,
+        ..Default::default()
+    };
+    if image_mirror()?.is_none() {
+        info!("No image mirror configured, nothing to verify");
+        return Ok(stats);
+    }
+
+    for kv in MIRROR_URLS.iter() {
+        let (sha256, url) = kv?;
+        let sha256 = String::from_utf8(sha256.to_vec())?;
+        let url = Url::parse(std::str::from_utf8(&url)?)?;
+        stats.checked += 1;
+
+        let result = match check_url(&url) {
+            UrlState::Ok => {
+                stats.ok += 1;
+                "ok"
+            }
+            UrlState::Unknown(reason) => {
+                warn!("Couldn't check {url}: {reason}");
+                stats.failed += 1;
+                "unknown"
+            }
+            UrlState::Gone => {
+                let repaired = find_by_hash(&sha256)?
+                    .ok_or_else(|| eyre!("no archived image with hash {sha256}"))
+                    .and_then(|archived| reupload(&archived));
+                match repaired {
+                    Ok(new_url) => {
+                        info!("{url} was gone, re-uploaded it as {new_url}");
+                        stats.reuploaded += 1;
+                        "reuploaded"
+                    }
+                    Err(err) => {
+                        warn!("{url} is gone and couldn't be re-uploaded: {err}");
+                        stats.failed += 1;
+                        "failed"
+                    }
+                }
+            }
+        };
+        CDN_VERIFY_CHECKS.with_label_values(&[result]).inc();
+    }
+
+    CDN_CACHE_DB.flush()?;
+    info!("CDN verify {stats}");
+    Ok(stats)
+}
+
+// runs `verify` if it's been at least `interval_secs` since the last time (0 turns it off)
+pub fn verify_if_due(interval_secs: u64) -> Result<()> {
+    if interval_secs == 0 {
+        return Ok(());
+    }
+    let now = time_format::now()?;
+    let last_verified_at = match VERIFY_STATE.get(LAST_VERIFY_KEY)? {
+        Some(value) => i64::from_be_bytes(value.as_ref().try_into()?),
+        None => 0,
+    };
+    if now - last_verified_at < interval_secs as i64 {
+        return Ok(());
+    }
+
+    // recorded up front, so a failing check waits for the next interval rather than the next run
+    VERIFY_STATE.insert(LAST_VERIFY_KEY, &now.to_be_bytes())?;
+    VERIFY_STATE.flush()?;
+    verify().map(|_| ())
+}
+
+// drops an image's mirrored url and uploads it again
+pub fn purge(image_id: usize) -> Result<Url> {
+    let archived = archived_image(image_id)?
+        .ok_or_else(|| eyre!("image {image_id} hasn't been archived yet"))?;
+    MIRROR_URLS.remove(&archived.sha256)?;
+    forget_legacy_cdn_url(image_id)?;
+
+    let url = reupload(&archived)?;
+    CDN_CACHE_DB.flush()?;
+    Ok(url)
+}
@@ -0,0 +1,270 @@
+use std::path::PathBuf;
+
+use clap::{Parser, Subcommand};
+use color_eyre::{Result, eyre::eyre};
+
+use crate::config::Destination;
+use crate::diff::{ItemDiff, compute_diff, format_prices, prices_changed};
+use crate::notifier::{build_notifier, deliver};
+use crate::scraper::{self, Region, ShopItemId, ShopItems};
+use crate::storage::{list_snapshots, load_latest_snapshot, load_snapshot};
+use crate::{cdn, history};
+
+#[derive(Parser)]
+#[command(about = "Tracks the Flavortown shop for price updates and new items")]
+pub struct Cli {
+    // no subcommand does a single scrape -> diff -> notify run
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Scrape, diff and notify on an interval until stopped
+    Daemon,
+    /// Scrape the shop once
+    Scrape {
+        /// Print the scraped items instead of storing them or sending notifications
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Show what changed between two stored snapshots
+    Diff {
+        snap_a: String,
+        snap_b: String,
+        /// Print the diff as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Print a single item from the latest snapshot (or the given one)
+    Show {
+        item_id: ShopItemId,
+        #[arg(long)]
+        snapshot: Option<String>,
+    },
+    /// List stored snapshots, oldest first
+    ListSnapshots,
+    /// Send notifications for a stored snapshot
+    Notify {
+        /// Snapshot to resend the diff for (compared against the snapshot before it)
+        #[arg(long)]
+        replay: String,
+        /// Where to send it, as `kind:url`
+        #[arg(long, value_parser = parse_destination)]
+        to: Destination,
+    },
+    /// Save a region's live shop page as a parser test fixture
+    RecordFixture {
+        #[arg(long, value_parser = parse_region)]
+        region: Region,
+        /// Defaults to tests/fixtures/shop/<region code>.html
+        #[arg(long)]
+        out: Option<PathBuf>,
+    },
+    /// Query or rebuild per-item price history
+    History {
+        #[command(subcommand)]
+        command: HistoryCommand,
+    },
+    /// Check or repair mirrored images
+    Cdn {
+        #[command(subcommand)]
+        command: CdnCommand,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum CdnCommand {
+    /// Re-hash images at flavortown and check every mirrored image still loads, re-uploading any that are gone
+    Verify,
+    /// Forget an image's mirrored url and upload it again
+    Purge { image_id: usize },
+}
+
+#[derive(Subcommand)]
+pub enum HistoryCommand {
+    /// Print every recorded price change for an item
+    Show {
+        item_id: ShopItemId,
+        /// Only show this region, by code (e.g. UK)
+        #[arg(long, value_parser = parse_region)]
+        region: Option<Region>,
+    },
+    /// Rebuild the price history from every stored snapshot
+    Backfill,
+}
+
+fn parse_destination(value: &str) -> Result<Destination> {
+    Destination::try_from(value.to_string())
+}
+
+fn parse_region(code: &str) -> Result<Region> {
+    Region::from_code(code).ok_or_else(|| eyre!("invalid region code {code:?}"))
+}
+
+fn load_named_snapshot(name: &str) -> Result<ShopItems> {
+    if name == "latest" {
+        return load_latest_snapshot()?.ok_or_else(|| eyre!("no snapshots stored yet"));
+    }
+    load_snapshot(name)
+}
+
+fn print_json<T: serde::Serialize>(value: &T) -> Result<()> {
+    println!("{}", serde_json::to_string_pretty(value)?);
+    Ok(())
+}
+
+fn print_diff(diff: &ItemDiff) {
+    if diff.is_empty() {
+        println!("No changes");
+        return;
+    }
+
+    for item in &diff.new_items {
+        println!(
+            "+ [{}] {} ({})",
+            item.id,
+            item.title,
+            format_prices(&item.prices)
+        );
+    }
+
+    for item in &diff.returning_items {
+        println!(
+            "+ [{}] {} ({}) - back in the shop",
+            item.id,
+            item.title,
+            format_prices(&item.prices)
+        );
+    }
+
+    for (old, new) in &diff.updated_items {
+        let mut changes = Vec::new();
+        if old.title != new.title {
+            changes.push(format!("title: {} → {}", old.title, new.title));
+        }
+        if prices_changed(&old.prices, &new.prices) {
+            changes.push(format!(
+                "price: {} → {}",
+                format_prices(&old.prices),
+                format_prices(&new.prices)
+            ));
+        }
+        if old.stock != new.stock {
+            changes.push(format!("stock: {} → {}", old.stock, new.stock));
+        }
+        if old.description != new.description {
+            changes.push("description changed".into());
+        }
+        if old.image_changed(new) {
+            changes.push("image changed".into());
+        }
+        println!("~ [{}] {}: {}", new.id, new.title, changes.join(", "));
+    }
+
+    for item in &diff.deleted_items {
+        println!("- [{}] {}", item.id, item.title);
+    }
+}
+
+pub fn scrape_dry_run() -> Result<()> {
+    let result = scraper::scrape_shop()?;
+    for warning in &result.parse_warnings {
+        eprintln!(
+            "warning: item {:?} in {} failed to parse: {}",
+            warning.shop_id, warning.region, warning.error
+        );
+    }
+    for region in &result.skipped_regions {
+        eprintln!("warning: skipped {region}, its prices are missing from the output");
+    }
+    print_json(&result.items)
+}
+
+pub fn diff(snap_a: &str, snap_b: &str, json: bool) -> Result<()> {
+    let diff = compute_diff(&load_named_snapshot(snap_a)?, &load_named_snapshot(snap_b)?);
+    if json {
+        print_json(&diff)
+    } else {
+        print_diff(&diff);
+        Ok(())
+    }
+}
+
+pub fn show(item_id: ShopItemId, snapshot: Option<&str>) -> Result<()> {
+    let items = load_named_snapshot(snapshot.unwrap_or("latest"))?;
+    let item = items
+        .into_iter()
+        .find(|item| item.id == item_id)
+        .ok_or_else(|| eyre!("no item with id {item_id}"))?;
+    print_json(&item)
+}
+
+pub fn print_snapshots() -> Result<()> {
+    for name in list_snapshots()? {
+        println!("{name}");
+    }
+    Ok(())
+}
+
+pub fn replay(snapshot: &str, to: &Destination) -> Result<()> {
+    let snapshots = list_snapshots()?;
+    let index = snapshots
+        .iter()
+        .position(|name| name == snapshot)
+        .ok_or_else(|| eyre!("no snapshot named {snapshot}"))?;
+    let previous = index.checked_sub(1).map(|i| &snapshots[i]).ok_or_else(|| {
+        eyre!("{snapshot} is the first snapshot, there's nothing to diff it against")
+    })?;
+
+    let diff = compute_diff(&load_snapshot(previous)?, &load_snapshot(snapshot)?);
+    if diff.is_empty() {
+        return Err(eyre!("{previous} and {snapshot} have no differences"));
+    }
+    print_diff(&diff);
+    deliver(build_notifier(to).as_ref(), &diff, &mut 0)
+}
+
+pub fn record_fixture(region: &Region, out: Option<PathBuf>) -> Result<()> {
+    let out = out.unwrap_or_else(|| {
+        PathBuf::from(format!(
+            "tests/fixtures/shop/{}.html",
+            region.code().to_lowercase()
+        ))
+    });
+    std::fs::write(&out, scraper::fetch_fixture_page(region)?)?;
+    println!(
+        "Wrote {} - check it for anything personal before committing it",
+        out.display()
+    );
+    Ok(())
+}
+
+pub fn history(command: &HistoryCommand) -> Result<()> {
+    match command {
+        HistoryCommand::Show { item_id, region } => {
+            history::print_item_history(*item_id, region.as_ref())
+        }
+        HistoryCommand::Backfill => history::backfill().map(|_| ()),
+    }
+}
+
+pub fn cdn(command: &CdnCommand) -> Result<()> {
+    match command {
+        CdnCommand::Verify => {
+            let stats = cdn::verify()?;
+            println!("{stats}");
+            if stats.failed > 0 {
+                return Err(eyre!(
+                    "{} mirrored images couldn't be verified",
+                    stats.failed
+                ));
+            }
+            Ok(())
+        }
+        CdnCommand::Purge { image_id } => {
+            println!("Re-uploaded image {image_id} as {}", cdn::purge(*image_id)?);
+            Ok(())
+        }
+    }
+}
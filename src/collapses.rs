@@ -0,0 +1,102 @@
+use std::collections::BTreeMap;
+
+use crate::alerts::send_admin_alert;
+use crate::config::CONFIG;
+use crate::scraper::Region;
+use crate::storage::CDN_CACHE_DB;
+use color_eyre::Result;
+use log::{info, warn};
+use once_cell::sync::Lazy;
+use sled::Tree;
+
+const COLLAPSES_TREE: &str = "collapses";
+const RUNS_KEY: &str = "runs";
+
+static COLLAPSES: Lazy<Tree> = Lazy::new(|| CDN_CACHE_DB.open_tree(COLLAPSES_TREE).unwrap());
+
+// bumps the run count of every region that's collapsed this run, forgetting the ones that
+// haven't - returns the regions that have now been collapsed for at least `confirm_runs` in a
+// row. A confirmed region keeps its count until a scrape no longer sees it collapsed, i.e. until
+// a snapshot without its old prices has been written, so a held back diff doesn't restart it
+fn count_runs(
+    runs: &mut BTreeMap<Region, u32>,
+    collapsed: &[Region],
+    confirm_runs: u32,
+) -> Vec<Region> {
+    runs.retain(|region, _| collapsed.contains(region));
+    let mut confirmed = Vec::new();
+    for region in collapsed {
+        let count = runs.entry(region.clone()).or_default();
+        *count += 1;
+        if *count >= confirm_runs {
+            confirmed.push(region.clone());
+        }
+    }
+    confirmed
+}
+
+// a collapsed region keeps its old prices for a few runs in case the page was just broken, but if
+// it stays that way the shop really has shrunk, so stop carrying it forward and let the diff through
+pub fn still_suspect(collapsed: &[Region]) -> Result<Vec<Region>> {
+    let mut runs: BTreeMap<Region, u32> = match COLLAPSES.get(RUNS_KEY)? {
+        Some(value) => serde_json::from_slice(&value)?,
+        None => BTreeMap::new(),
+    };
+    let confirmed = count_runs(&mut runs, collapsed, CONFIG.region_collapse_confirm_runs);
+
+    for region in confirmed
+        .iter()
+        .filter(|region| runs[*region] == CONFIG.region_collapse_confirm_runs)
+    {
+        info!(
+            "{region} has stayed collapsed for {} runs, accepting its new item count",
+            CONFIG.region_collapse_confirm_runs
+        );
+        send_admin_alert(&format!(
+            "{region} ({}) has had far fewer items for {} runs in a row, so the drop has been accepted.",
+            region.code(),
+            CONFIG.region_collapse_confirm_runs
+        ));
+    }
+    for (region, count) in runs
+        .iter()
+        .filter(|(region, _)| !confirmed.contains(region))
+    {
+        warn!(
+            "{region} has collapsed for {count}/{} runs, keeping its old prices for now",
+            CONFIG.region_collapse_confirm_runs
+        );
+    }
+
+    COLLAPSES.insert(RUNS_KEY, serde_json::to_vec(&runs)?)?;
+    COLLAPSES.flush()?;
+    Ok(collapsed
+        .iter()
+        .filter(|region| !confirmed.contains(region))
+        .cloned()
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_a_lasting_collapse() {
+        let (us, uk) = (Region::new("US"), Region::new("UK"));
+        let mut runs = BTreeMap::new();
+
+        assert!(count_runs(&mut runs, &[us.clone(), uk.clone()], 3).is_empty());
+        assert!(count_runs(&mut runs, std::slice::from_ref(&us), 3).is_empty());
+        // the uk page recovered, so its count starts over
+        assert!(!runs.contains_key(&uk));
+        let confirmed = count_runs(&mut runs, &[us.clone(), uk.clone()], 3);
+        assert_eq!(confirmed, std::slice::from_ref(&us));
+        assert_eq!(runs, BTreeMap::from([(us.clone(), 3), (uk.clone(), 1)]));
+        // stays accepted while the diff hasn't made it into a snapshot yet...
+        assert_eq!(count_runs(&mut runs, std::slice::from_ref(&us), 3), [us]);
+        // ...and is forgotten once the snapshot has caught up
+        assert!(count_runs(&mut runs, &[], 3).is_empty());
+        assert!(runs.is_empty());
+    }
+}
@@ -1,6 +1,6 @@
 use std::path::PathBuf;
 
-use color_eyre::eyre::Context;
+use color_eyre::eyre::{Context, eyre};
 use once_cell::sync::Lazy;
 use reqwest::Url;
 use serde::Deserialize;
@@ -8,13 +8,160 @@ use serde::Deserialize;
 #[derive(Deserialize)]
 pub struct Config {
     pub cookie: String,
-    pub webhook_url: Url,
+    pub webhook_url: Option<Url>,
+    pub discord_webhook_url: Option<Url>,
+    #[serde(default)]
+    pub notifiers: Vec<Destination>,
+    // operator alerts (expired cookie etc), as `kind:url`
+    pub admin_notifier: Option<Destination>,
     #[serde(default = "default_user_agent")]
     pub user_agent: String,
     #[serde(default = "default_base_url")]
     pub base_url: Url,
+    #[serde(default)]
+    pub image_mirror: MirrorKind,
+    #[serde(default = "default_cdn_url")]
+    pub cdn_url: Url,
+    pub cdn_token: Option<String>,
+    // where the local mirror keeps images, defaults to `images` in the storage path
+    pub image_dir: Option<PathBuf>,
+    // the base url mirrored images are served from, for the local and s3 mirrors
+    pub image_public_url: Option<Url>,
+    pub s3_endpoint: Option<Url>,
+    pub s3_bucket: Option<String>,
+    #[serde(default = "default_s3_region")]
+    pub s3_region: String,
+    pub s3_access_key_id: Option<String>,
+    pub s3_secret_access_key: Option<String>,
+    // 0 turns the periodic check off
+    #[serde(default = "default_cdn_verify_interval_secs")]
+    pub cdn_verify_interval_secs: u64,
     #[serde(default = "default_storage_path")]
     pub storage_path: PathBuf,
+    #[serde(default = "default_scrape_interval_secs")]
+    pub scrape_interval_secs: u64,
+    #[serde(default = "default_scrape_jitter_secs")]
+    pub scrape_jitter_secs: u64,
+    #[serde(default = "default_request_retries")]
+    pub request_retries: u32,
+    #[serde(default = "default_request_backoff_ms")]
+    pub request_backoff_ms: u64,
+    #[serde(default = "default_request_min_interval_ms")]
+    pub request_min_interval_ms: u64,
+    #[serde(default = "default_max_parse_failure_percent")]
+    pub max_parse_failure_percent: u32,
+    #[serde(default = "default_max_region_drop_percent")]
+    pub max_region_drop_percent: u32,
+    // regions smaller than this aren't checked for collapses
+    #[serde(default = "default_region_collapse_min_items")]
+    pub region_collapse_min_items: usize,
+    #[serde(default = "default_region_collapse_confirm_runs")]
+    pub region_collapse_confirm_runs: u32,
+    #[serde(default = "default_removal_confirm_runs")]
+    pub removal_confirm_runs: u32,
+    // 0 turns the time-based check off
+    #[serde(default)]
+    pub removal_confirm_mins: i64,
+    #[serde(default = "default_anomaly_percent")]
+    pub anomaly_percent: u32,
+    #[serde(default = "default_anomaly_confirm_runs")]
+    pub anomaly_confirm_runs: u32,
+    #[serde(default = "default_http_addr")]
+    pub http_addr: String,
+}
+
+#[derive(Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum MirrorKind {
+    #[default]
+    HackClub,
+    Local,
+    S3,
+    // keep linking to flavortown's own activestorage urls
+    None,
+}
+
+impl MirrorKind {
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            Self::HackClub => "hackclub",
+            Self::Local => "local",
+            Self::S3 => "s3",
+            Self::None => "none",
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy)]
+pub enum DestinationKind {
+    Slack,
+    Discord,
+    Json,
+}
+
+// `kind:url`, e.g. `discord:https://discord.com/api/webhooks/...`
+#[derive(Deserialize, Clone)]
+#[serde(try_from = "String")]
+pub struct Destination {
+    pub kind: DestinationKind,
+    pub url: Url,
+}
+
+impl DestinationKind {
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            Self::Slack => "slack",
+            Self::Discord => "discord",
+            Self::Json => "json",
+        }
+    }
+}
+
+impl std::fmt::Display for Destination {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.kind.as_str(), self.url)
+    }
+}
+
+impl TryFrom<String> for Destination {
+    type Error = color_eyre::Report;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let (kind, url) = value
+            .split_once(':')
+            .ok_or_else(|| eyre!("notifier {value:?} should look like `kind:url`"))?;
+        let kind = match kind {
+            "slack" => DestinationKind::Slack,
+            "discord" => DestinationKind::Discord,
+            "json" => DestinationKind::Json,
+            _ => return Err(eyre!("unknown notifier kind {kind:?}")),
+        };
+        Ok(Self {
+            kind,
+            url: url.parse()?,
+        })
+    }
+}
+
+impl Config {
+    // WEBHOOK_URL and DISCORD_WEBHOOK_URL predate NOTIFIERS, so fold them in
+    pub fn destinations(&self) -> Vec<Destination> {
+        let legacy = [
+            (DestinationKind::Slack, &self.webhook_url),
+            (DestinationKind::Discord, &self.discord_webhook_url),
+        ];
+        legacy
+            .into_iter()
+            .filter_map(|(kind, url)| url.clone().map(|url| Destination { kind, url }))
+            .chain(self.notifiers.iter().cloned())
+            .collect()
+    }
+
+    pub fn image_dir(&self) -> PathBuf {
+        self.image_dir
+            .clone()
+            .unwrap_or_else(|| self.storage_path.join("images"))
+    }
 }
 
 fn default_user_agent() -> String {
@@ -25,10 +172,74 @@ fn default_base_url() -> Url {
     Url::parse("https://flavortown.hackclub.com/").unwrap()
 }
 
+fn default_cdn_url() -> Url {
+    Url::parse("https://cdn.hackclub.com/").unwrap()
+}
+
+fn default_s3_region() -> String {
+    "us-east-1".into()
+}
+
+const fn default_cdn_verify_interval_secs() -> u64 {
+    24 * 60 * 60
+}
+
 fn default_storage_path() -> PathBuf {
     std::env::current_dir().unwrap().join("flavortown-storage")
 }
 
+const fn default_scrape_interval_secs() -> u64 {
+    300
+}
+
+const fn default_scrape_jitter_secs() -> u64 {
+    30
+}
+
+const fn default_request_retries() -> u32 {
+    3
+}
+
+const fn default_request_backoff_ms() -> u64 {
+    500
+}
+
+const fn default_request_min_interval_ms() -> u64 {
+    250
+}
+
+const fn default_max_parse_failure_percent() -> u32 {
+    10
+}
+
+const fn default_max_region_drop_percent() -> u32 {
+    50
+}
+
+const fn default_region_collapse_min_items() -> usize {
+    5
+}
+
+const fn default_region_collapse_confirm_runs() -> u32 {
+    3
+}
+
+const fn default_removal_confirm_runs() -> u32 {
+    3
+}
+
+const fn default_anomaly_percent() -> u32 {
+    50
+}
+
+const fn default_anomaly_confirm_runs() -> u32 {
+    3
+}
+
+fn default_http_addr() -> String {
+    "0.0.0.0:8080".into()
+}
+
 pub static CONFIG: Lazy<Config> = Lazy::new(|| {
     envy::from_env::<Config>()
         .wrap_err("failed to load config")
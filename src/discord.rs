@@ -0,0 +1,299 @@
+use crate::diff::{ItemDiff, format_prices, prices_changed};
+use crate::scraper::{CLIENT, ShopItem};
+use color_eyre::Result;
+use log::info;
+use reqwest::Url;
+use serde::Serialize;
+
+// https://discord.com/developers/docs/resources/message#embed-object-embed-limits
+const MAX_EMBEDS_PER_MESSAGE: usize = 10;
+const MAX_CHARS_PER_MESSAGE: usize = 6000;
+const MAX_TITLE_CHARS: usize = 256;
+const MAX_DESCRIPTION_CHARS: usize = 4096;
+
+const COLOR_NEW: u32 = 0x57f287;
+const COLOR_UPDATED: u32 = 0xfee75c;
+const COLOR_DELETED: u32 = 0xed4245;
+
+#[derive(Serialize)]
+struct EmbedImage {
+    url: Url,
+}
+
+#[derive(Serialize)]
+struct Embed {
+    title: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    url: Option<Url>,
+    description: String,
+    color: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    image: Option<EmbedImage>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    thumbnail: Option<EmbedImage>,
+}
+
+impl Embed {
+    fn char_count(&self) -> usize {
+        self.title.chars().count() + self.description.chars().count()
+    }
+}
+
+#[derive(Serialize)]
+struct WebhookMessage {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    content: Option<String>,
+    embeds: Vec<Embed>,
+}
+
+fn truncate(text: &str, max_chars: usize) -> String {
+    if text.chars().count() <= max_chars {
+        return text.to_string();
+    }
+    let mut truncated: String = text.chars().take(max_chars - 1).collect();
+    truncated.push('…');
+    truncated
+}
+
+fn embed(title: String, description: String, color: u32) -> Embed {
+    Embed {
+        title: truncate(&title, MAX_TITLE_CHARS),
+        url: None,
+        description: truncate(&description, MAX_DESCRIPTION_CHARS),
+        color,
+        image: None,
+        thumbnail: None,
+    }
+}
+
+// item text comes straight from the shop, so it mustn't be able to break our own formatting
+fn escape_markdown(text: &str) -> String {
+    text.chars()
+        .flat_map(|c| match c {
+            '\\' | '*' | '_' | '~' | '`' | '|' => vec!['\\', c],
+            _ => vec![c],
+        })
+        .collect()
+}
+
+fn item_description(desc: &str) -> String {
+    if desc.is_empty() {
+        String::new()
+    } else {
+        format!("*{}*\n", escape_markdown(desc))
+    }
+}
+
+fn render_new_item(emoji: &str, item: &ShopItem) -> Embed {
+    let description = format!(
+        "{}**Price:** {} shells\n**Stock:** {}",
+        item_description(&item.description),
+        format_prices(&item.prices),
+        item.stock
+    );
+
+    Embed {
+        url: Some(item.buy_link()),
+        image: Some(EmbedImage {
+            url: item.image_url.clone(),
+        }),
+        ..embed(format!("{emoji} {}", item.title), description, COLOR_NEW)
+    }
+}
+
+fn render_deleted_item(item: &ShopItem) -> Embed {
+    let description = format!(
+        "{}**Price:** {} shells",
+        item_description(&item.description),
+        format_prices(&item.prices)
+    );
+
+    Embed {
+        thumbnail: Some(EmbedImage {
+            url: item.image_url.clone(),
+        }),
+        ..embed(format!("🗑️ {}", item.title), description, COLOR_DELETED)
+    }
+}
+
+fn render_updated_item(old: &ShopItem, new: &ShopItem) -> Embed {
+    let title = if old.title != new.title {
+        format!("{} → {}", old.title, new.title)
+    } else {
+        new.title.clone()
+    };
+
+    let price = if prices_changed(&old.prices, &new.prices) {
+        format!(
+            "{} → {}",
+            format_prices(&old.prices),
+            format_prices(&new.prices)
+        )
+    } else {
+        format_prices(&new.prices)
+    };
+
+    let stock = if old.stock != new.stock {
+        format!("{} → {}", old.stock, new.stock)
+    } else {
+        new.stock.to_string()
+    };
+
+    let description = if old.description != new.description {
+        let old_desc = if old.description.is_empty() {
+            "*no description*"
+        } else {
+            &escape_markdown(&old.description)
+        };
+        let new_desc = if new.description.is_empty() {
+            "*no description*"
+        } else {
+            &escape_markdown(&new.description)
+        };
+        format!("{old_desc} → {new_desc}\n")
+    } else {
+        item_description(&new.description)
+    };
+
+    let image_changed = old.image_changed(new);
+    let image = if image_changed {
+        "\n**Image changed**"
+    } else {
+        ""
+    };
+
+    Embed {
+        url: Some(new.buy_link()),
+        image: Some(EmbedImage {
+            url: new.image_url.clone(),
+        }),
+        // discord only allows one full-size image per embed, so the old one goes in the corner
+        thumbnail: image_changed.then(|| EmbedImage {
+            url: old.image_url.clone(),
+        }),
+        ..embed(
+            format!("✏️ {title}"),
+            format!("{description}**Price:** {price} shells\n**Stock:** {stock}{image}"),
+            COLOR_UPDATED,
+        )
+    }
+}
+
+fn chunk_embeds(embeds: Vec<Embed>) -> Vec<Vec<Embed>> {
+    let mut chunks: Vec<Vec<Embed>> = Vec::new();
+    let mut current_chars = 0;
+
+    for embed in embeds {
+        let chars = embed.char_count();
+        match chunks.last_mut() {
+            Some(chunk)
+                if chunk.len() < MAX_EMBEDS_PER_MESSAGE
+                    && current_chars + chars <= MAX_CHARS_PER_MESSAGE =>
+            {
+                current_chars += chars;
+                chunk.push(embed);
+            }
+            _ => {
+                current_chars = chars;
+                chunks.push(vec![embed]);
+            }
+        }
+    }
+
+    chunks
+}
+
+pub fn send_webhook_notifications(
+    webhook_url: &Url,
+    diff: &ItemDiff,
+    sent: &mut usize,
+) -> Result<()> {
+    let mut embeds = Vec::new();
+    embeds.extend(
+        diff.new_items
+            .iter()
+            .map(|item| render_new_item("🆕", item)),
+    );
+    embeds.extend(
+        diff.returning_items
+            .iter()
+            .map(|item| render_new_item("🔙", item)),
+    );
+    embeds.extend(
+        diff.updated_items
+            .iter()
+            .map(|(old, new)| render_updated_item(old, new)),
+    );
+    embeds.extend(diff.deleted_items.iter().map(render_deleted_item));
+
+    let summary = diff.summary();
+
+    for (i, embeds) in chunk_embeds(embeds).into_iter().enumerate().skip(*sent) {
+        let message = WebhookMessage {
+            content: (i == 0).then(|| summary.clone()),
+            embeds,
+        };
+
+        CLIENT
+            .post(webhook_url.clone())
+            .json(&message)
+            .send()?
+            .error_for_status()?;
+        *sent = i + 1;
+    }
+
+    info!("Successfully sent Discord notifications");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn embed_with_chars(chars: usize) -> Embed {
+        embed(String::new(), "x".repeat(chars), COLOR_NEW)
+    }
+
+    #[test]
+    fn truncates_long_titles_and_descriptions() {
+        let long = embed("t".repeat(300), "d".repeat(5000), COLOR_NEW);
+        assert_eq!(long.title.chars().count(), MAX_TITLE_CHARS);
+        assert!(long.title.ends_with('…'));
+        assert_eq!(long.description.chars().count(), MAX_DESCRIPTION_CHARS);
+        assert!(long.description.ends_with('…'));
+
+        assert_eq!(truncate("short", MAX_TITLE_CHARS), "short");
+        assert_eq!(truncate(&"é".repeat(256), MAX_TITLE_CHARS), "é".repeat(256));
+    }
+
+    #[test]
+    fn chunks_by_embed_count_and_total_length() {
+        let sizes: Vec<_> = chunk_embeds((0..25).map(|_| embed_with_chars(10)).collect())
+            .iter()
+            .map(Vec::len)
+            .collect();
+        assert_eq!(sizes, [10, 10, 5]);
+
+        // two 4000 char embeds would go over the 6000 chars a message allows
+        let chunks = chunk_embeds((0..3).map(|_| embed_with_chars(4000)).collect());
+        assert_eq!(chunks.len(), 3);
+        let chunks = chunk_embeds(vec![
+            embed_with_chars(4000),
+            embed_with_chars(2000),
+            embed_with_chars(1),
+        ]);
+        assert_eq!(chunks.iter().map(Vec::len).collect::<Vec<_>>(), [2, 1]);
+        for chunk in &chunks {
+            assert!(chunk.iter().map(Embed::char_count).sum::<usize>() <= MAX_CHARS_PER_MESSAGE);
+        }
+    }
+
+    #[test]
+    fn escapes_markdown_in_descriptions() {
+        assert_eq!(
+            item_description("**free** stickers_for_all"),
+            "*\\*\\*free\\*\\* stickers\\_for\\_all*\n"
+        );
+        assert_eq!(item_description(""), "");
+    }
+}
@@ -0,0 +1,144 @@
+use std::collections::{BTreeMap, HashSet};
+
+use crate::scraper::{Region, ShopItemId, ShopItems};
+use crate::storage::{CDN_CACHE_DB, list_snapshots, load_snapshot, snapshot_time};
+use color_eyre::{Result, eyre::eyre};
+use log::info;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use sled::Tree;
+
+const PRICE_HISTORY_TREE: &str = "price-history";
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct PricePoint {
+    pub observed_at: String,
+    // `None` once the item stops being sold in the region
+    pub price: Option<u32>,
+}
+
+pub type PriceHistory = BTreeMap<String, Vec<PricePoint>>;
+
+static PRICE_HISTORY: Lazy<Tree> =
+    Lazy::new(|| CDN_CACHE_DB.open_tree(PRICE_HISTORY_TREE).unwrap());
+
+// keys are the big-endian item id followed by the region code, so an item's regions sit next to each other
+fn history_key(id: ShopItemId, region: &Region) -> Vec<u8> {
+    let mut key = (id as u64).to_be_bytes().to_vec();
+    key.extend_from_slice(region.code().as_bytes());
+    key
+}
+
+fn parse_history_key(key: &[u8]) -> Result<(ShopItemId, Region)> {
+    let (id, code) = key
+        .split_at_checked(8)
+        .ok_or_else(|| eyre!("price history key is too short"))?;
+    let id = u64::from_be_bytes(id.try_into()?) as ShopItemId;
+    let code = std::str::from_utf8(code)?;
+    let region =
+        Region::from_code(code).ok_or_else(|| eyre!("invalid region {code:?} in price history"))?;
+    Ok((id, region))
+}
+
+fn append_point(key: Vec<u8>, observed_at: &str, price: Option<u32>) -> Result<()> {
+    let mut points: Vec<PricePoint> = match PRICE_HISTORY.get(&key)? {
+        Some(value) => serde_json::from_slice(&value)?,
+        None => Vec::new(),
+    };
+    if points.last().map(|p| p.price) == Some(price) {
+        return Ok(());
+    }
+    // a region we've never seen the item in has no history to close off
+    if points.is_empty() && price.is_none() {
+        return Ok(());
+    }
+
+    points.push(PricePoint {
+        observed_at: observed_at.to_string(),
+        price,
+    });
+    PRICE_HISTORY.insert(key, serde_json::to_vec(&points)?)?;
+    Ok(())
+}
+
+pub fn record(items: &ShopItems, observed_at: &str) -> Result<()> {
+    let mut seen = HashSet::new();
+
+    for item in items {
+        for (region, price) in &item.prices {
+            let key = history_key(item.id, region);
+            append_point(key.clone(), observed_at, Some(*price))?;
+            seen.insert(key);
+        }
+    }
+
+    // anything we've tracked before but didn't see this time has gone away
+    for key in PRICE_HISTORY.iter().keys() {
+        let key = key?.to_vec();
+        if !seen.contains(&key) {
+            append_point(key, observed_at, None)?;
+        }
+    }
+
+    PRICE_HISTORY.flush()?;
+    Ok(())
+}
+
+pub fn backfill() -> Result<usize> {
+    PRICE_HISTORY.clear()?;
+
+    let snapshots = list_snapshots()?;
+    for name in &snapshots {
+        let observed_at =
+            snapshot_time(name).ok_or_else(|| eyre!("can't get a timestamp from {name}"))?;
+        record(&load_snapshot(name)?, &observed_at)?;
+    }
+
+    info!("Rebuilt price history from {} snapshots", snapshots.len());
+    Ok(snapshots.len())
+}
+
+pub fn item_history(id: ShopItemId, region: Option<&Region>) -> Result<PriceHistory> {
+    let mut history = PriceHistory::new();
+
+    for kv in PRICE_HISTORY.scan_prefix((id as u64).to_be_bytes()) {
+        let (key, value) = kv?;
+        let (_, key_region) = parse_history_key(&key)?;
+        if region.is_some_and(|r| *r != key_region) {
+            continue;
+        }
+        history.insert(
+            key_region.code().to_string(),
+            serde_json::from_slice(&value)?,
+        );
+    }
+
+    Ok(history)
+}
+
+pub fn seen_before(id: ShopItemId) -> Result<bool> {
+    Ok(PRICE_HISTORY
+        .scan_prefix((id as u64).to_be_bytes())
+        .next()
+        .transpose()?
+        .is_some())
+}
+
+pub fn print_item_history(id: ShopItemId, region: Option<&Region>) -> Result<()> {
+    let history = item_history(id, region)?;
+    if history.is_empty() {
+        println!("No price history for item {id}");
+        return Ok(());
+    }
+
+    for (code, points) in history {
+        println!("{}:", Region::new(&code));
+        for point in points {
+            match point.price {
+                Some(price) => println!("  {}  {price}", point.observed_at),
+                None => println!("  {}  no longer sold", point.observed_at),
+            }
+        }
+    }
+    Ok(())
+}
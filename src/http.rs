@@ -0,0 +1,90 @@
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::config::CONFIG;
+use crate::metrics::REQUEST_RETRIES;
+use color_eyre::{Result, eyre::eyre};
+use log::warn;
+use once_cell::sync::Lazy;
+use reqwest::StatusCode;
+use reqwest::blocking::{RequestBuilder, Response};
+use reqwest::header::RETRY_AFTER;
+
+// don't let a silly Retry-After stall the whole run
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(5 * 60);
+
+static LAST_REQUEST: Lazy<Mutex<Option<Instant>>> = Lazy::new(|| Mutex::new(None));
+
+// holds the lock while sleeping, so concurrent callers queue up behind each other
+fn wait_for_turn() {
+    let mut last_request = LAST_REQUEST.lock().unwrap();
+    let min_interval = Duration::from_millis(CONFIG.request_min_interval_ms);
+    if let Some(elapsed) = last_request.map(|at| at.elapsed())
+        && elapsed < min_interval
+    {
+        thread::sleep(min_interval - elapsed);
+    }
+    *last_request = Some(Instant::now());
+}
+
+fn backoff(attempt: u32) -> Duration {
+    let base = CONFIG
+        .request_backoff_ms
+        .saturating_mul(1 << attempt.min(16));
+    // "equal" jitter: somewhere between half and all of the backoff, so retries from parallel
+    // image downloads don't line up but still wait at least half as long
+    Duration::from_millis(fastrand::u64(base / 2..=base))
+}
+
+fn retry_after(res: &Response) -> Option<Duration> {
+    let secs = res
+        .headers()
+        .get(RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim()
+        .parse()
+        .ok()?;
+    Some(Duration::from_secs(secs).min(MAX_RETRY_AFTER))
+}
+
+fn is_retryable(status: StatusCode) -> bool {
+    status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS
+}
+
+// sends a request, retrying connection errors, 5xxs and 429s with exponential backoff.
+// anything else (including redirects) is handed straight back to the caller.
+pub fn send(request: RequestBuilder) -> Result<Response> {
+    let mut attempt = 0;
+    loop {
+        let attempt_request = request
+            .try_clone()
+            .ok_or_else(|| eyre!("can't retry a request with a streaming body"))?;
+        wait_for_turn();
+
+        let delay = match attempt_request.send() {
+            Ok(res) if !is_retryable(res.status()) => return Ok(res),
+            Ok(res) if attempt < CONFIG.request_retries => {
+                let delay = retry_after(&res).unwrap_or_else(|| backoff(attempt));
+                warn!(
+                    "{} returned {}, retrying in {delay:?}",
+                    res.url(),
+                    res.status()
+                );
+                delay
+            }
+            Ok(res) => return Ok(res),
+            Err(err) if attempt < CONFIG.request_retries && !err.is_builder() => {
+                let delay = backoff(attempt);
+                warn!("Request failed ({err}), retrying in {delay:?}");
+                delay
+            }
+            Err(err) => return Err(err.into()),
+        };
+
+        REQUEST_RETRIES.inc();
+        thread::sleep(delay);
+        attempt += 1;
+    }
+}
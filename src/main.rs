@@ -1,46 +1,632 @@
-use color_eyre::Result;
-use log::{info, warn};
+use std::collections::BTreeSet;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
 
+use clap::Parser;
+use color_eyre::{Result, eyre::eyre};
+use log::{error, info, warn};
+use signal_hook::consts::{SIGINT, SIGTERM};
+
+use crate::cli::{Cli, Command};
+use crate::config::CONFIG;
+
+mod absences;
+mod alerts;
+mod anomaly;
+mod archive;
+mod cdn;
+mod cli;
+mod collapses;
 mod config;
 mod diff;
+mod discord;
+mod history;
+mod http;
+mod metrics;
+mod mirror;
+#[cfg(test)]
+mod mock;
+mod notifier;
+mod outbox;
 mod rails;
 mod scraper;
+mod server;
+mod slack;
 mod storage;
 
-fn main() -> Result<()> {
-    dotenvy::dotenv().ok();
-    color_eyre::install()?;
-    env_logger::init();
+fn write_snapshot(items: &scraper::ShopItems) -> Result<()> {
+    let snap_name = storage::write_new_snapshot(items)?;
+    let observed_at = storage::snapshot_time(&snap_name)
+        .ok_or_else(|| eyre!("can't get a timestamp from {snap_name}"))?;
+    history::record(items, &observed_at)
+}
 
+fn run_once() -> Result<usize> {
+    let scraped = scrape_and_queue();
+    // diffs already in the outbox go out whatever happened to this run's scrape
+    let delivered = outbox::deliver_pending();
+    let item_count = scraped?;
+    delivered?;
+    Ok(item_count)
+}
+
+fn scrape_and_queue() -> Result<usize> {
     info!("Starting scrape job...");
-    let items = scraper::scrape()?;
+    let scrape_result = scraper::scrape();
+    // the scrape's own error is the one worth returning, trouble keeping track of the alert is only logged
+    let alerted = match &scrape_result {
+        Ok(_) => alerts::session_ok(),
+        Err(err) => match err.downcast_ref::<scraper::ScrapeError>() {
+            Some(expired @ scraper::ScrapeError::LoginRequired { .. }) => {
+                alerts::session_expired(expired)
+            }
+            _ => Ok(()),
+        },
+    };
+    if let Err(err) = alerted {
+        error!("Failed to update the session alert: {err:?}");
+    }
+    let scraper::ScrapeResult {
+        mut items,
+        parse_warnings,
+        regions,
+        regions_discovered,
+        skipped_regions,
+    } = scrape_result?;
+    if regions_discovered {
+        alerts::regions_seen(&regions)?;
+    } else {
+        alerts::region_dropdown_missing()?;
+    }
     let old_snap = storage::load_latest_snapshot()?;
+    let mut collapsed_regions = Vec::new();
+    // changes the removal and collapse guards have already waited out, which the anomaly hold
+    // mustn't make wait all over again
+    let mut confirmed_ids = BTreeSet::new();
+    if let Some(old_snap) = &old_snap {
+        scraper::carry_forward_unparsed(&mut items, old_snap, &parse_warnings);
+        scraper::carry_forward_regions(&mut items, old_snap, &skipped_regions);
+
+        collapsed_regions = scraper::collapsed_regions(
+            &items,
+            old_snap,
+            CONFIG.max_region_drop_percent,
+            CONFIG.region_collapse_min_items,
+        );
+        // a region that's left the dropdown really has lost all its items
+        collapsed_regions.retain(|region| regions.iter().any(|(r, _)| r == region));
+        let still_suspect = collapses::still_suspect(&collapsed_regions)?;
+        confirmed_ids.extend(
+            old_snap
+                .iter()
+                .filter(|item| {
+                    item.prices.keys().any(|region| {
+                        collapsed_regions.contains(region) && !still_suspect.contains(region)
+                    })
+                })
+                .map(|item| item.id),
+        );
+        collapsed_regions = still_suspect;
+        scraper::carry_forward_regions(&mut items, old_snap, &collapsed_regions);
+        confirmed_ids.extend(absences::debounce_removals(&mut items, old_snap)?);
+    }
+    server::record_scrape_issues(&parse_warnings, &skipped_regions, &collapsed_regions);
+    let item_count = items.len();
 
     match old_snap {
         Some(old_snap) => {
-            let item_diff = diff::compute_diff(&old_snap, &items);
+            let mut item_diff = diff::compute_diff(&old_snap, &items);
+            absences::label_returning(&mut item_diff)?;
+
+            if anomaly::hold_back(&item_diff, old_snap.len(), &confirmed_ids)? {
+                info!("Not sending or storing this run's diff until it's confirmed");
+            } else if item_diff.is_empty() {
+                if items == old_snap {
+                    info!("Items haven't changed");
+                } else {
+                    // e.g. `cdn verify` re-uploaded an image - nothing to tell anyone, but the
+                    // snapshot shouldn't keep pointing at the dead url
+                    info!("Only image urls changed, updating the snapshot without notifying");
+                    write_snapshot(&items)?;
+                }
+            } else {
+                info!(
+                    "Found {} new, {} updated, {} deleted items",
+                    item_diff.new_items.len(),
+                    item_diff.updated_items.len(),
+                    item_diff.deleted_items.len()
+                );
 
-            if item_diff.is_empty() {
-                info!("Items haven't changed - exiting!");
-                return Ok(());
+                // once the diff is safely in the outbox the snapshot can move forward,
+                // delivery (and retrying it) is the outbox's problem
+                outbox::enqueue(&item_diff)?;
+                write_snapshot(&items)?;
             }
+        }
+        None => {
+            warn!("No old snapshot found, writing first snapshot");
+            write_snapshot(&items)?;
+        }
+    }
 
-            info!(
-                "Found {} new, {} updated, {} deleted items",
-                item_diff.new_items.len(),
-                item_diff.updated_items.len(),
-                item_diff.deleted_items.len()
-            );
+    Ok(item_count)
+}
+
+fn next_run_delay() -> Duration {
+    let jitter = fastrand::u64(0..=CONFIG.scrape_jitter_secs);
+    Duration::from_secs(CONFIG.scrape_interval_secs + jitter)
+}
+
+fn run_daemon() -> Result<()> {
+    let shutdown = Arc::new(AtomicBool::new(false));
+    signal_hook::flag::register(SIGTERM, Arc::clone(&shutdown))?;
+    signal_hook::flag::register(SIGINT, Arc::clone(&shutdown))?;
+
+    metrics::init();
+    server::spawn()?;
 
-            diff::send_webhook_notifications(&item_diff)?;
-            storage::write_new_snapshot(items)?;
+    info!(
+        "Running as a daemon every {}s (+ up to {}s jitter)",
+        CONFIG.scrape_interval_secs, CONFIG.scrape_jitter_secs
+    );
+
+    while !shutdown.load(Ordering::Relaxed) {
+        let started_at = Instant::now();
+        let result = run_once();
+        server::record_run(started_at.elapsed(), &result);
+        if let Err(err) = result {
+            error!("Scrape job failed: {err:?}");
         }
-        None => {
-            warn!("No old snapshot found, writing first snapshot and exiting");
-            storage::write_new_snapshot(items)?;
+
+        if let Err(err) = cdn::verify_if_due(CONFIG.cdn_verify_interval_secs) {
+            error!("CDN verify failed: {err:?}");
+        }
+
+        // sleep in small steps so a SIGTERM doesn't have to wait out the whole interval
+        let wake_at = Instant::now() + next_run_delay();
+        while !shutdown.load(Ordering::Relaxed) && Instant::now() < wake_at {
+            thread::sleep(Duration::from_millis(500));
         }
     }
 
+    info!("Received shutdown signal, exiting");
     Ok(())
 }
 
+fn main() -> Result<()> {
+    dotenvy::dotenv().ok();
+    color_eyre::install()?;
+    env_logger::init();
+
+    let command = Cli::parse().command;
+    if matches!(
+        command,
+        None | Some(Command::Scrape { .. } | Command::Daemon | Command::Cdn { .. })
+    ) {
+        // fail now rather than on the first image upload, deep inside a scrape
+        storage::image_mirror()?;
+    }
+
+    match command {
+        None | Some(Command::Scrape { dry_run: false }) => run_once().map(|_| ()),
+        Some(Command::Scrape { dry_run: true }) => cli::scrape_dry_run(),
+        Some(Command::Daemon) => run_daemon(),
+        Some(Command::Diff {
+            snap_a,
+            snap_b,
+            json,
+        }) => cli::diff(&snap_a, &snap_b, json),
+        Some(Command::Show { item_id, snapshot }) => cli::show(item_id, snapshot.as_deref()),
+        Some(Command::ListSnapshots) => cli::print_snapshots(),
+        Some(Command::Notify { replay, to }) => cli::replay(&replay, &to),
+        Some(Command::RecordFixture { region, out }) => cli::record_fixture(&region, out),
+        Some(Command::History { command }) => cli::history(&command),
+        Some(Command::Cdn { command }) => cli::cdn(&command),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{MOCK_COOKIE, MockFlavortown, MockItem};
+
+    fn summary(message: &serde_json::Value) -> &str {
+        message["text"].as_str().unwrap()
+    }
+
+    // CONFIG is process-wide, so this is the only test that gets to point it at the mock
+    #[test]
+    fn scrape_diff_notify_cycles_against_mock_flavortown() {
+        let shop = MockFlavortown::start(vec![
+            MockItem::new(1, "Stickers", 15),
+            MockItem::new(2, "Raspberry Pi", 250),
+        ]);
+        let storage_path =
+            std::env::temp_dir().join(format!("flavortown-e2e-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&storage_path);
+
+        // SAFETY: nothing else in the test binary reads these variables
+        unsafe {
+            std::env::set_var("COOKIE", MOCK_COOKIE);
+            std::env::set_var("BASE_URL", format!("{}/", shop.base_url));
+            std::env::set_var("CDN_URL", format!("{}/", shop.base_url));
+            std::env::set_var("CDN_TOKEN", "test-token");
+            std::env::set_var("WEBHOOK_URL", shop.hook_url("slack"));
+            std::env::set_var(
+                "ADMIN_NOTIFIER",
+                format!("slack:{}", shop.hook_url("admin")),
+            );
+            std::env::set_var("STORAGE_PATH", &storage_path);
+            std::env::set_var("REQUEST_BACKOFF_MS", "10");
+            std::env::set_var("REQUEST_MIN_INTERVAL_MS", "0");
+            std::env::set_var("REMOVAL_CONFIRM_RUNS", "2");
+            // the region collapse guard gets in the way of the mass removal below
+            std::env::set_var("REGION_COLLAPSE_MIN_ITEMS", "1000");
+        }
+
+        // first run only writes the baseline snapshot
+        assert_eq!(run_once().unwrap(), 2);
+        assert!(shop.webhook_messages("slack").is_empty());
+        assert_eq!(shop.cdn_uploads(), 2);
+        // and keeps its own copy of every image
+        let archived = archive::archived_image(1001).unwrap().unwrap();
+        assert_eq!(archived.read().unwrap(), b"picture of item 1");
+
+        // a price change and a new item
+        let mut pi = MockItem::new(2, "Raspberry Pi", 250);
+        pi.prices.insert(scraper::Region::new("UK"), 300);
+        shop.set_items(vec![
+            MockItem::new(1, "Stickers", 15),
+            pi.clone(),
+            MockItem::new(3, "Keyboard", 900),
+        ]);
+        assert_eq!(run_once().unwrap(), 3);
+        let messages = shop.webhook_messages("slack");
+        assert_eq!(messages.len(), 1);
+        assert_eq!(
+            summary(&messages[0]),
+            "Shop update: 1 new, 1 updated, 0 removed"
+        );
+        // images we've already mirrored come from the cache
+        assert_eq!(shop.cdn_uploads(), 3);
+
+        // nothing changed, nothing sent - even when flavortown has a couple of hiccups
+        shop.fail_next_requests(2);
+        assert_eq!(run_once().unwrap(), 3);
+        assert_eq!(shop.webhook_messages("slack").len(), 1);
+
+        // a region that won't switch is skipped, and its prices aren't reported as gone
+        shop.set_stuck_region(Some("UK"));
+        assert_eq!(run_once().unwrap(), 3);
+        assert_eq!(shop.webhook_messages("slack").len(), 1);
+        let latest = storage::load_latest_snapshot().unwrap().unwrap();
+        assert_eq!(latest[1].prices[&scraper::Region::new("UK")], 300);
+        shop.set_stuck_region(None);
+
+        // a removed item has to stay gone for two runs before it's announced
+        shop.set_items(vec![pi.clone(), MockItem::new(3, "Keyboard", 900)]);
+        assert_eq!(run_once().unwrap(), 3);
+        assert_eq!(shop.webhook_messages("slack").len(), 1);
+        assert_eq!(run_once().unwrap(), 2);
+        let messages = shop.webhook_messages("slack");
+        assert_eq!(messages.len(), 2);
+        assert_eq!(
+            summary(&messages[1]),
+            "Shop update: 0 new, 0 updated, 1 removed"
+        );
+
+        // an expired cookie alerts the operator once, not on every run - and a failed alert is
+        // retried next run without hiding the scrape's own error
+        shop.set_session_expired(true);
+        shop.fail_webhook_after(0);
+        for _ in 0..3 {
+            let err = run_once().unwrap_err();
+            assert!(matches!(
+                err.downcast_ref::<scraper::ScrapeError>(),
+                Some(scraper::ScrapeError::LoginRequired { .. })
+            ));
+        }
+        let alerts = shop.webhook_messages("admin");
+        assert_eq!(alerts.len(), 1);
+        assert!(summary(&alerts[0]).contains("COOKIE has expired"));
+
+        shop.set_session_expired(false);
+        run_once().unwrap();
+        let alerts = shop.webhook_messages("admin");
+        assert_eq!(alerts.len(), 2);
+        assert!(summary(&alerts[1]).contains("resumed"));
+
+        // flavortown opens up a new region and renames an old one
+        shop.set_regions(&[
+            ("US", "United States"),
+            ("EU", "Europe"),
+            ("UK", "United Kingdom"),
+            ("IN", "India"),
+            ("CA", "Canada"),
+            ("AU", "Australia"),
+            ("XX", "Rest of World"),
+            ("JP", "Japan"),
+        ]);
+        let mut keyboard = MockItem::new(3, "Keyboard", 900);
+        keyboard.prices.insert(scraper::Region::new("JP"), 1100);
+        shop.set_items(vec![pi, keyboard]);
+        assert_eq!(run_once().unwrap(), 2);
+        let alerts = shop.webhook_messages("admin");
+        assert_eq!(alerts.len(), 3);
+        assert!(summary(&alerts[2]).contains("Japan (JP) added"));
+        assert!(summary(&alerts[2]).contains("EU (EU) renamed to Europe"));
+        let messages = shop.webhook_messages("slack");
+        assert_eq!(
+            summary(messages.last().unwrap()),
+            "Shop update: 0 new, 1 updated, 0 removed"
+        );
+
+        // every item changing at once is held back until it's been seen on 3 runs
+        shop.set_items(vec![
+            MockItem::new(2, "Raspberry Pi 5", 250),
+            MockItem::new(3, "Mechanical Keyboard", 900),
+        ]);
+        let sent_before = shop.webhook_messages("slack").len();
+        for _ in 0..2 {
+            run_once().unwrap();
+            assert_eq!(shop.webhook_messages("slack").len(), sent_before);
+        }
+        let alerts = shop.webhook_messages("admin");
+        assert_eq!(alerts.len(), 4);
+        assert!(summary(&alerts[3]).contains("holding back"));
+
+        run_once().unwrap();
+        assert_eq!(shop.webhook_messages("slack").len(), sent_before + 1);
+        assert_eq!(shop.webhook_messages("admin").len(), 5);
+
+        // an item we've seen before isn't new when it comes back
+        shop.set_items(vec![
+            MockItem::new(1, "Stickers", 15),
+            MockItem::new(2, "Raspberry Pi 5", 250),
+            MockItem::new(3, "Mechanical Keyboard", 900),
+        ]);
+        run_once().unwrap();
+        assert_eq!(
+            summary(shop.webhook_messages("slack").last().unwrap()),
+            "Shop update: 0 new, 1 back in the shop, 0 updated, 0 removed"
+        );
+
+        // the same picture re-uploaded under a new blob isn't an update...
+        let mut pi = MockItem::new(2, "Raspberry Pi 5", 250);
+        pi.image_blob_id = 5002;
+        shop.set_items(vec![
+            MockItem::new(1, "Stickers", 15),
+            pi.clone(),
+            MockItem::new(3, "Mechanical Keyboard", 900),
+        ]);
+        let sent_before = shop.webhook_messages("slack").len();
+        run_once().unwrap();
+        assert_eq!(shop.webhook_messages("slack").len(), sent_before);
+
+        // ...but a new picture is
+        pi.image = "a different picture".into();
+        pi.image_blob_id = 5003;
+        shop.set_items(vec![
+            MockItem::new(1, "Stickers", 15),
+            pi,
+            MockItem::new(3, "Mechanical Keyboard", 900),
+        ]);
+        run_once().unwrap();
+        let messages = shop.webhook_messages("slack");
+        let message = messages.last().unwrap();
+        assert_eq!(summary(message), "Shop update: 0 new, 1 updated, 0 removed");
+        assert!(message.to_string().contains("Image changed"));
+
+        // a mirrored image that's disappeared gets re-uploaded from the archive
+        let uploads_before = shop.cdn_uploads();
+        let latest = storage::load_latest_snapshot().unwrap().unwrap();
+        shop.delete_cdn_file(latest[0].image_url.as_str());
+        let stats = cdn::verify().unwrap();
+        assert_eq!(stats.reuploaded, 1);
+        assert_eq!(stats.failed, 0);
+        assert_eq!(stats.ok, stats.checked - 1);
+        assert_eq!(shop.cdn_uploads(), uploads_before + 1);
+
+        // the daemon's periodic check remembers when it last ran, even across restarts
+        let ok_checks = || metrics::CDN_VERIFY_CHECKS.with_label_values(&["ok"]).get();
+        let checks_before = ok_checks();
+        cdn::verify_if_due(60 * 60).unwrap();
+        assert!(ok_checks() > checks_before);
+        let checks_before = ok_checks();
+        cdn::verify_if_due(60 * 60).unwrap();
+        assert_eq!(ok_checks(), checks_before);
+
+        let purged_url = cdn::purge(latest[0].image_id).unwrap();
+        assert_eq!(shop.cdn_uploads(), uploads_before + 2);
+        let sent_before = shop.webhook_messages("slack").len();
+        run_once().unwrap();
+        // the snapshot picks up the new url, without telling anyone
+        assert_eq!(
+            storage::load_latest_snapshot().unwrap().unwrap()[0].image_url,
+            purged_url
+        );
+        assert_eq!(shop.webhook_messages("slack").len(), sent_before);
+
+        // two new items sharing a picture under different blobs: each blob is downloaded once,
+        // and the picture is only uploaded once
+        let mut lamp = MockItem::new(4, "Lamp", 120);
+        lamp.image = "a shared picture".into();
+        let mut lamp_deluxe = MockItem::new(5, "Lamp Deluxe", 180);
+        lamp_deluxe.image = "a shared picture".into();
+        let (downloads_before, uploads_before) = (shop.image_downloads(), shop.cdn_uploads());
+        let shop_items = vec![
+            MockItem::new(1, "Stickers", 15),
+            MockItem::new(3, "Mechanical Keyboard", 900),
+            lamp,
+            lamp_deluxe,
+        ];
+        shop.set_items(shop_items.clone());
+        run_once().unwrap();
+        assert_eq!(shop.image_downloads(), downloads_before + 2);
+        assert_eq!(shop.cdn_uploads(), uploads_before + 1);
+        let latest = storage::load_latest_snapshot().unwrap().unwrap();
+        let image_url = |id| &latest.iter().find(|item| item.id == id).unwrap().image_url;
+        assert_eq!(image_url(4), image_url(5));
+
+        // a failed delivery fails the run, but waiting to retry it doesn't
+        let mut items = shop_items.clone();
+        items[0] = MockItem::new(1, "Stickers", 20);
+        shop.set_items(items);
+        shop.fail_webhook_after(0);
+        assert!(run_once().is_err());
+        run_once().unwrap();
+
+        // a long diff that fails partway through picks up from the message that failed
+        let latest = storage::load_latest_snapshot().unwrap().unwrap();
+        let diff = diff::compute_diff(
+            &Vec::new(),
+            &(100..140)
+                .map(|id| scraper::ShopItem {
+                    id,
+                    ..latest[0].clone()
+                })
+                .collect(),
+        );
+        let destination =
+            config::Destination::try_from(format!("slack:{}", shop.hook_url("chunked"))).unwrap();
+        let slack = notifier::build_notifier(&destination);
+        let mut sent = 0;
+        shop.fail_webhook_after(1);
+        assert!(notifier::deliver(slack.as_ref(), &diff, &mut sent).is_err());
+        assert_eq!(sent, 1);
+        notifier::deliver(slack.as_ref(), &diff, &mut sent).unwrap();
+        let messages = shop.webhook_messages("chunked");
+        assert!(messages.len() > 2);
+        assert_eq!(sent, messages.len());
+        for (i, message) in messages.iter().enumerate() {
+            assert_eq!(
+                summary(message),
+                format!(
+                    "Shop update: 40 new, 0 updated, 0 removed ({}/{sent})",
+                    i + 1
+                )
+            );
+        }
+
+        // callers racing on the same new image download and upload it once between them
+        let poster = MockItem::new(6, "Poster", 40);
+        let poster_url: reqwest::Url = shop.image_url(&poster).parse().unwrap();
+        shop.set_items(vec![poster.clone()]);
+        shop.set_image_delay(Duration::from_millis(200));
+        let (downloads_before, uploads_before) = (shop.image_downloads(), shop.cdn_uploads());
+        let urls: Vec<_> = thread::scope(|scope| {
+            let handles: Vec<_> = (0..4)
+                .map(|_| scope.spawn(|| storage::upload_to_cdn(poster.image_blob_id, &poster_url)))
+                .collect();
+            handles
+                .into_iter()
+                .map(|h| h.join().unwrap().unwrap())
+                .collect()
+        });
+        assert!(urls.iter().all(|url| *url == urls[0]));
+        assert_eq!(shop.image_downloads(), downloads_before + 1);
+        assert_eq!(shop.cdn_uploads(), uploads_before + 1);
+
+        // flavortown swapping the bytes behind an existing blob is caught by the next verify
+        shop.set_image_delay(Duration::ZERO);
+        run_once().unwrap();
+        let old_hash = archive::archived_image(poster.image_blob_id)
+            .unwrap()
+            .unwrap()
+            .sha256;
+        let mut poster = poster;
+        poster.image = "a redrawn poster".into();
+        shop.set_items(vec![poster.clone()]);
+        let downloads_before = shop.image_downloads();
+        let stats = cdn::verify().unwrap();
+        assert_eq!(stats.changed_at_source, 1);
+        // only the poster is still in the shop, so it's the only image downloaded again
+        assert_eq!(shop.image_downloads(), downloads_before + 1);
+        let archived = archive::archived_image(poster.image_blob_id)
+            .unwrap()
+            .unwrap();
+        assert_ne!(archived.sha256, old_hash);
+        assert_eq!(archived.read().unwrap(), b"a redrawn poster");
+        assert_ne!(
+            storage::upload_to_cdn(poster.image_blob_id, &poster_url).unwrap(),
+            urls[0]
+        );
+
+        // a dropdown we can't read falls back to the known regions, and the operator hears about it once
+        let dropdown_alerts = || {
+            shop.webhook_messages("admin")
+                .iter()
+                .filter(|alert| summary(alert).contains("region dropdown"))
+                .count()
+        };
+        shop.hide_region_options(true);
+        // the regions kept in memory are gone after a restart, but the ones the dropdown last offered aren't
+        scraper::Region::set_all(&[(scraper::Region::new("US"), "United States".into())]);
+        // ...even if the admin webhook is down the first time, which doesn't fail the run
+        shop.fail_webhook_after(0);
+        run_once().unwrap();
+        assert_eq!(dropdown_alerts(), 0);
+        assert!(scraper::Region::all().contains(&scraper::Region::new("JP")));
+        for _ in 0..2 {
+            run_once().unwrap();
+        }
+        assert_eq!(dropdown_alerts(), 1);
+        shop.hide_region_options(false);
+        run_once().unwrap();
+        assert_eq!(dropdown_alerts(), 2);
+        assert!(
+            shop.webhook_messages("admin")
+                .iter()
+                .any(|alert| summary(alert).contains("dropdown again"))
+        );
+
+        // most of the shop really going away gets through the removal debounce, and the anomaly
+        // hold doesn't make the already confirmed removals wait all over again
+        let mut shop_items = vec![poster];
+        shop_items.extend((20..29).map(|id| MockItem::new(id, "Sticker pack", 10)));
+        shop.set_items(shop_items.clone());
+        run_once().unwrap();
+        let (sent_before, alerts_before) = (
+            shop.webhook_messages("slack").len(),
+            shop.webhook_messages("admin").len(),
+        );
+        shop_items.truncate(3);
+        shop.set_items(shop_items);
+        run_once().unwrap();
+        assert_eq!(shop.webhook_messages("slack").len(), sent_before);
+        run_once().unwrap();
+        assert_eq!(shop.webhook_messages("slack").len(), sent_before + 1);
+        assert_eq!(shop.webhook_messages("admin").len(), alerts_before);
+        let messages = shop.webhook_messages("slack");
+        assert_eq!(
+            summary(messages.last().unwrap()),
+            "Shop update: 0 new, 0 updated, 7 removed"
+        );
+        assert_eq!(storage::load_latest_snapshot().unwrap().unwrap().len(), 3);
+        run_once().unwrap();
+        assert_eq!(shop.webhook_messages("slack").len(), messages.len());
+
+        // the json notifier posts the whole diff in one go, however many times it's retried
+        let json = notifier::build_notifier(&config::Destination {
+            kind: config::DestinationKind::Json,
+            url: shop.hook_url("json").parse().unwrap(),
+        });
+        let snapshot = storage::load_latest_snapshot().unwrap().unwrap();
+        let item_diff = diff::compute_diff(&Vec::new(), &snapshot);
+        let mut sent = 0;
+        for _ in 0..2 {
+            notifier::deliver(json.as_ref(), &item_diff, &mut sent).unwrap();
+        }
+        assert_eq!(sent, 1);
+        json.alert("hello").unwrap();
+        let messages = shop.webhook_messages("json");
+        assert_eq!(messages.len(), 2);
+        let delivered: diff::ItemDiff = serde_json::from_value(messages[0].clone()).unwrap();
+        assert_eq!(delivered, item_diff);
+        assert_eq!(messages[1], serde_json::json!({ "alert": "hello" }));
+
+        let _ = std::fs::remove_dir_all(&storage_path);
+    }
+}
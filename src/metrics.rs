@@ -0,0 +1,116 @@
+use color_eyre::Result;
+use once_cell::sync::Lazy;
+use prometheus::{
+    Encoder, HistogramVec, IntCounter, IntCounterVec, IntGauge, IntGaugeVec, TextEncoder,
+    register_histogram_vec, register_int_counter, register_int_counter_vec, register_int_gauge,
+    register_int_gauge_vec,
+};
+
+pub static REGION_SCRAPE_DURATION: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
+        "flavortown_region_scrape_duration_seconds",
+        "Time taken to switch to and scrape a single shop region",
+        &["region"]
+    )
+    .unwrap()
+});
+
+pub static REGION_ITEMS: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec!(
+        "flavortown_region_items",
+        "Number of items found in a region on the last scrape",
+        &["region"]
+    )
+    .unwrap()
+});
+
+pub static PARSE_FAILURES: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "flavortown_parse_failures_total",
+        "Shop item cards that couldn't be parsed"
+    )
+    .unwrap()
+});
+
+pub static CDN_UPLOADS: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!("flavortown_cdn_uploads_total", "Images uploaded to the CDN").unwrap()
+});
+
+pub static CDN_CACHE_HITS: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "flavortown_cdn_cache_hits_total",
+        "Images that were already in the CDN cache"
+    )
+    .unwrap()
+});
+
+pub static CDN_VERIFY_CHECKS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "flavortown_cdn_verify_checks_total",
+        "Mirrored image urls checked by `cdn verify`, by result",
+        &["result"]
+    )
+    .unwrap()
+});
+
+pub static REGION_DISCOVERY_FALLBACKS: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "flavortown_region_discovery_fallbacks_total",
+        "Scrapes where the region dropdown couldn't be read, so the last known regions were used"
+    )
+    .unwrap()
+});
+
+pub static ADMIN_ALERT_FAILURES: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "flavortown_admin_alert_failures_total",
+        "Operator alerts that couldn't be delivered to the ADMIN_NOTIFIER"
+    )
+    .unwrap()
+});
+
+pub static REQUEST_RETRIES: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "flavortown_request_retries_total",
+        "Requests to flavortown that were retried after a failure"
+    )
+    .unwrap()
+});
+
+pub static WEBHOOK_SENDS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "flavortown_webhook_sends_total",
+        "Webhook deliveries, by destination and result",
+        &["destination", "result"]
+    )
+    .unwrap()
+});
+
+pub static LAST_SNAPSHOT_WRITE: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge!(
+        "flavortown_last_snapshot_write_timestamp_seconds",
+        "Unix timestamp of the last successful snapshot write"
+    )
+    .unwrap()
+});
+
+// metrics are registered lazily, so touch them all up-front to have them show up before the first scrape
+pub fn init() {
+    Lazy::force(&REGION_SCRAPE_DURATION);
+    Lazy::force(&REGION_ITEMS);
+    Lazy::force(&PARSE_FAILURES);
+    Lazy::force(&CDN_UPLOADS);
+    Lazy::force(&CDN_CACHE_HITS);
+    Lazy::force(&CDN_VERIFY_CHECKS);
+    Lazy::force(&REGION_DISCOVERY_FALLBACKS);
+    Lazy::force(&ADMIN_ALERT_FAILURES);
+    Lazy::force(&REQUEST_RETRIES);
+    Lazy::force(&WEBHOOK_SENDS);
+    Lazy::force(&LAST_SNAPSHOT_WRITE);
+}
+
+pub fn render() -> Result<String> {
+    let mut buf = Vec::new();
+    TextEncoder::new().encode(&prometheus::gather(), &mut buf)?;
+    Ok(String::from_utf8(buf)?)
+}
@@ -0,0 +1,378 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use base64::prelude::*;
+use sha2::{Digest, Sha256};
+use tiny_http::{Header, Method, Request, Response, Server};
+
+use crate::scraper::{Region, ShopItemId};
+
+pub const MOCK_COOKIE: &str = "_flavortown_session=mock";
+const MOCK_CSRF_TOKEN: &str = "mock-csrf-token";
+const MOCK_REGIONS: &[(&str, &str)] = &[
+    ("US", "United States"),
+    ("EU", "EU"),
+    ("UK", "United Kingdom"),
+    ("IN", "India"),
+    ("CA", "Canada"),
+    ("AU", "Australia"),
+    ("XX", "Rest of World"),
+];
+
+#[derive(Clone)]
+pub struct MockItem {
+    pub id: ShopItemId,
+    pub title: String,
+    pub description: String,
+    pub prices: HashMap<Region, u32>,
+    pub image_blob_id: usize,
+    // what the image "file" contains
+    pub image: String,
+}
+
+impl MockItem {
+    pub fn new(id: ShopItemId, title: &str, price: u32) -> Self {
+        Self {
+            id,
+            title: title.into(),
+            description: format!("{title} description"),
+            prices: MOCK_REGIONS
+                .iter()
+                .map(|(code, _)| (Region::new(code), price))
+                .collect(),
+            image_blob_id: id + 1000,
+            image: format!("picture of item {id}"),
+        }
+    }
+}
+
+#[derive(Default)]
+struct MockState {
+    items: Vec<MockItem>,
+    // what the region dropdown offers, as (code, name)
+    regions: Vec<(String, String)>,
+    // leave the options out of the region dropdown, like a markup change would
+    hide_region_options: bool,
+    // flavortown keeps the selected region in the session, so we key it by cookie too
+    selected_regions: HashMap<String, Region>,
+    session_expired: bool,
+    // the next this-many flavortown requests get a 503
+    failures_remaining: usize,
+    // switching to this region code silently leaves you on the US page
+    stuck_region: Option<String>,
+    image_downloads: usize,
+    // how long image downloads take, so concurrent downloads actually overlap
+    image_delay: Duration,
+    cdn_uploads: usize,
+    // paths of uploaded files the cdn still serves
+    cdn_files: HashSet<String>,
+    // keyed by `bucket/key`
+    s3_objects: HashMap<String, Vec<u8>>,
+    // after this many more webhook posts succeed, the next one gets a 500
+    webhooks_before_failure: Option<usize>,
+    // keyed by the last path segment of `/hooks/<name>`
+    webhook_messages: HashMap<String, Vec<serde_json::Value>>,
+}
+
+// a stand-in for flavortown, the CDN and a slack webhook, all on one local port
+pub struct MockFlavortown {
+    pub base_url: String,
+    state: Arc<Mutex<MockState>>,
+}
+
+fn image_path(blob_id: usize) -> String {
+    let blob = BASE64_STANDARD.encode(format!(
+        r#"{{"_rails":{{"data":{blob_id},"pur":"blob_id"}}}}"#
+    ));
+    format!(
+        "/rails/active_storage/representations/redirect/{blob}--sig/variant--sig/item-{blob_id}.png"
+    )
+}
+
+fn blob_path(blob_id: usize) -> String {
+    format!("/rails/active_storage/blobs/proxy/{blob_id}--sig/item-{blob_id}.png")
+}
+
+fn render_shop(base_url: &str, state: &MockState, region: &Region) -> String {
+    let items = &state.items;
+    let cards: String = items
+        .iter()
+        .filter_map(|item| {
+            let price = item.prices.get(region)?;
+            Some(format!(
+                r#"<div class="shop-item-card" data-shop-id="{}">
+  <div class="shop-item-card__image"><img src="{base_url}{}"></div>
+  <h4>{}</h4>
+  <div class="shop-item-card__description"><p>{}</p></div>
+  <span class="shop-item-card__price">{price}</span>
+</div>"#,
+                item.id,
+                image_path(item.image_blob_id),
+                item.title,
+                item.description
+            ))
+        })
+        .collect();
+    let selected = state
+        .regions
+        .iter()
+        .find(|(code, _)| code == region.code())
+        .map_or(region.code(), |(_, name)| name.as_str());
+    let options: String = state
+        .regions
+        .iter()
+        .filter(|_| !state.hide_region_options)
+        .map(|(code, name)| {
+            format!(r#"<button class="dropdown__option" data-region="{code}">{name}</button>"#)
+        })
+        .collect();
+
+    format!(
+        r#"<html><head><meta name="csrf-token" content="{MOCK_CSRF_TOKEN}"></head><body>
+<div class="dropdown">
+<button class="dropdown__button"><span class="dropdown__selected"><span class="dropdown__char-span">{selected}</span></span></button>
+<div class="dropdown__menu">{options}</div>
+</div>
+{cards}
+</body></html>"#
+    )
+}
+
+fn header<'a>(request: &'a Request, name: &str) -> Option<&'a str> {
+    request
+        .headers()
+        .iter()
+        .find(|h| h.field.as_str().as_str().eq_ignore_ascii_case(name))
+        .map(|h| h.value.as_str())
+}
+
+impl MockFlavortown {
+    pub fn start(items: Vec<MockItem>) -> Self {
+        let server = Server::http("127.0.0.1:0").unwrap();
+        let base_url = format!("http://{}", server.server_addr().to_ip().unwrap());
+        let state = Arc::new(Mutex::new(MockState {
+            items,
+            regions: MOCK_REGIONS
+                .iter()
+                .map(|(code, name)| ((*code).to_string(), (*name).to_string()))
+                .collect(),
+            ..Default::default()
+        }));
+
+        let thread_state = Arc::clone(&state);
+        let thread_base_url = base_url.clone();
+        thread::spawn(move || {
+            for mut request in server.incoming_requests() {
+                if request.url().starts_with("/rails/active_storage/") {
+                    let delay = thread_state.lock().unwrap().image_delay;
+                    thread::sleep(delay);
+                }
+                let mut body = Vec::new();
+                request.as_reader().read_to_end(&mut body).ok();
+                let response = handle(&thread_base_url, &thread_state, &request, &body);
+                request.respond(response).ok();
+            }
+        });
+
+        Self { base_url, state }
+    }
+
+    pub fn set_items(&self, items: Vec<MockItem>) {
+        self.state.lock().unwrap().items = items;
+    }
+
+    pub fn hide_region_options(&self, hide: bool) {
+        self.state.lock().unwrap().hide_region_options = hide;
+    }
+
+    pub fn set_regions(&self, regions: &[(&str, &str)]) {
+        self.state.lock().unwrap().regions = regions
+            .iter()
+            .map(|(code, name)| ((*code).to_string(), (*name).to_string()))
+            .collect();
+    }
+
+    pub fn image_url(&self, item: &MockItem) -> String {
+        format!("{}{}", self.base_url, image_path(item.image_blob_id))
+    }
+
+    pub fn set_image_delay(&self, delay: Duration) {
+        self.state.lock().unwrap().image_delay = delay;
+    }
+
+    pub fn image_downloads(&self) -> usize {
+        self.state.lock().unwrap().image_downloads
+    }
+
+    pub fn cdn_uploads(&self) -> usize {
+        self.state.lock().unwrap().cdn_uploads
+    }
+
+    pub fn delete_cdn_file(&self, url: &str) {
+        let path = url.trim_start_matches(&self.base_url);
+        self.state.lock().unwrap().cdn_files.remove(path);
+    }
+
+    pub fn s3_object(&self, path: &str) -> Option<Vec<u8>> {
+        self.state.lock().unwrap().s3_objects.get(path).cloned()
+    }
+
+    pub fn set_session_expired(&self, expired: bool) {
+        self.state.lock().unwrap().session_expired = expired;
+    }
+
+    pub fn fail_next_requests(&self, count: usize) {
+        self.state.lock().unwrap().failures_remaining = count;
+    }
+
+    pub fn fail_webhook_after(&self, successes: usize) {
+        self.state.lock().unwrap().webhooks_before_failure = Some(successes);
+    }
+
+    pub fn set_stuck_region(&self, code: Option<&str>) {
+        self.state.lock().unwrap().stuck_region = code.map(String::from);
+    }
+
+    pub fn hook_url(&self, name: &str) -> String {
+        format!("{}/hooks/{name}", self.base_url)
+    }
+
+    pub fn webhook_messages(&self, name: &str) -> Vec<serde_json::Value> {
+        self.state
+            .lock()
+            .unwrap()
+            .webhook_messages
+            .get(name)
+            .cloned()
+            .unwrap_or_default()
+    }
+}
+
+fn handle(
+    base_url: &str,
+    state: &Mutex<MockState>,
+    request: &Request,
+    body: &[u8],
+) -> Response<std::io::Cursor<Vec<u8>>> {
+    let mut state = state.lock().unwrap();
+    let cookie = header(request, "Cookie").unwrap_or_default().to_string();
+    let path = request.url().split('?').next().unwrap_or_default();
+
+    if state.failures_remaining > 0 && path.starts_with("/shop") {
+        state.failures_remaining -= 1;
+        return Response::from_string("service unavailable").with_status_code(503);
+    }
+
+    match (request.method(), path) {
+        (_, "/shop" | "/shop/update_region") if state.session_expired => Response::from_string("")
+            .with_status_code(302)
+            .with_header(Header::from_bytes("Location", format!("{base_url}/login")).unwrap()),
+        (Method::Get, "/shop") => {
+            let region = state
+                .selected_regions
+                .get(&cookie)
+                .cloned()
+                .unwrap_or_else(|| Region::new("US"));
+            Response::from_string(render_shop(base_url, &state, &region))
+                .with_header(Header::from_bytes("Content-Type", "text/html").unwrap())
+        }
+        (Method::Patch, "/shop/update_region") => {
+            if header(request, "X-CSRF-Token") != Some(MOCK_CSRF_TOKEN) {
+                return Response::from_string("bad csrf token").with_status_code(422);
+            }
+            let region = std::str::from_utf8(body)
+                .unwrap_or_default()
+                .strip_prefix("region=")
+                .filter(|code| state.regions.iter().any(|(c, _)| c == code))
+                .map(|code| {
+                    if state.stuck_region.as_deref() == Some(code) {
+                        Region::new("US")
+                    } else {
+                        Region::new(code)
+                    }
+                });
+            match region {
+                Some(region) => {
+                    state.selected_regions.insert(cookie, region);
+                    Response::from_string("ok")
+                }
+                None => Response::from_string("unknown region").with_status_code(422),
+            }
+        }
+        // like rails, representation urls redirect to the blob that's actually served
+        (Method::Get, path) if path.starts_with("/rails/active_storage/representations/") => {
+            match state
+                .items
+                .iter()
+                .find(|item| image_path(item.image_blob_id) == path)
+            {
+                Some(item) => Response::from_string("").with_status_code(302).with_header(
+                    Header::from_bytes("Location", blob_path(item.image_blob_id)).unwrap(),
+                ),
+                None => Response::from_string("not found").with_status_code(404),
+            }
+        }
+        (Method::Get, path) if path.starts_with("/rails/active_storage/blobs/") => {
+            state.image_downloads += 1;
+            match state
+                .items
+                .iter()
+                .find(|item| blob_path(item.image_blob_id) == path)
+            {
+                Some(item) => Response::from_data(item.image.clone().into_bytes()),
+                None => Response::from_string("not found").with_status_code(404),
+            }
+        }
+        (Method::Post, "/api/file") => {
+            state.cdn_uploads += 1;
+            let path = format!("/cdn/{}.png", state.cdn_uploads);
+            let url = format!("{base_url}{path}");
+            state.cdn_files.insert(path);
+            Response::from_string(serde_json::json!({ "url": url }).to_string())
+        }
+        (Method::Get | Method::Head, path) if path.starts_with("/cdn/") => {
+            if state.cdn_files.contains(path) {
+                Response::from_string("")
+            } else {
+                Response::from_string("not found").with_status_code(404)
+            }
+        }
+        (Method::Post, path)
+            if path.starts_with("/hooks/") && state.webhooks_before_failure == Some(0) =>
+        {
+            state.webhooks_before_failure = None;
+            Response::from_string("internal error").with_status_code(500)
+        }
+        (Method::Post, path) if path.starts_with("/hooks/") => match serde_json::from_slice(body) {
+            Ok(message) => {
+                if let Some(successes) = &mut state.webhooks_before_failure {
+                    *successes -= 1;
+                }
+                let name = path.trim_start_matches("/hooks/").to_string();
+                state
+                    .webhook_messages
+                    .entry(name)
+                    .or_default()
+                    .push(message);
+                Response::from_string("ok")
+            }
+            Err(_) => Response::from_string("invalid_payload").with_status_code(400),
+        },
+        // a minio-ish bucket: any signed PUT whose payload hash matches is accepted
+        (Method::Put, path) => {
+            let signed = header(request, "Authorization")
+                .is_some_and(|auth| auth.starts_with("AWS4-HMAC-SHA256 Credential="));
+            let payload_hash = hex::encode(Sha256::digest(body));
+            if !signed || header(request, "x-amz-content-sha256") != Some(payload_hash.as_str()) {
+                return Response::from_string("SignatureDoesNotMatch").with_status_code(403);
+            }
+            state
+                .s3_objects
+                .insert(path.trim_start_matches('/').to_string(), body.to_vec());
+            Response::from_string("")
+        }
+        _ => Response::from_string("not found").with_status_code(404),
+    }
+}
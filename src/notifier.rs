@@ -0,0 +1,115 @@
+use crate::config::{Destination, DestinationKind};
+use crate::diff::ItemDiff;
+use crate::metrics::WEBHOOK_SENDS;
+use crate::scraper::CLIENT;
+use crate::{discord, slack};
+use color_eyre::Result;
+use log::{error, info};
+use reqwest::Url;
+use serde_json::json;
+
+pub trait Notifier: Send + Sync {
+    // used for logs and the `destination` metric label
+    fn kind(&self) -> &'static str;
+    // big diffs go out as several messages - `sent` is how many already have, so a retry
+    // picks up where the last attempt failed, and it's bumped as each one goes out
+    fn notify(&self, diff: &ItemDiff, sent: &mut usize) -> Result<()>;
+    // a plain one-off message for whoever runs the tracker, rather than shop updates
+    fn alert(&self, message: &str) -> Result<()>;
+}
+
+fn post_json(webhook_url: &Url, body: &serde_json::Value) -> Result<()> {
+    CLIENT
+        .post(webhook_url.clone())
+        .json(body)
+        .send()?
+        .error_for_status()?;
+    Ok(())
+}
+
+pub struct SlackNotifier {
+    webhook_url: Url,
+}
+
+impl Notifier for SlackNotifier {
+    fn kind(&self) -> &'static str {
+        "slack"
+    }
+
+    fn notify(&self, diff: &ItemDiff, sent: &mut usize) -> Result<()> {
+        slack::send_webhook_notifications(&self.webhook_url, diff, sent)
+    }
+
+    fn alert(&self, message: &str) -> Result<()> {
+        post_json(&self.webhook_url, &json!({ "text": message }))
+    }
+}
+
+pub struct DiscordNotifier {
+    webhook_url: Url,
+}
+
+impl Notifier for DiscordNotifier {
+    fn kind(&self) -> &'static str {
+        "discord"
+    }
+
+    fn notify(&self, diff: &ItemDiff, sent: &mut usize) -> Result<()> {
+        discord::send_webhook_notifications(&self.webhook_url, diff, sent)
+    }
+
+    fn alert(&self, message: &str) -> Result<()> {
+        post_json(&self.webhook_url, &json!({ "content": message }))
+    }
+}
+
+// posts the raw `ItemDiff` as JSON, for anything that wants to do its own rendering
+pub struct JsonNotifier {
+    webhook_url: Url,
+}
+
+impl Notifier for JsonNotifier {
+    fn kind(&self) -> &'static str {
+        "json"
+    }
+
+    fn notify(&self, diff: &ItemDiff, sent: &mut usize) -> Result<()> {
+        if *sent == 0 {
+            post_json(&self.webhook_url, &serde_json::to_value(diff)?)?;
+            *sent = 1;
+        }
+        Ok(())
+    }
+
+    fn alert(&self, message: &str) -> Result<()> {
+        post_json(&self.webhook_url, &json!({ "alert": message }))
+    }
+}
+
+pub fn build_notifier(destination: &Destination) -> Box<dyn Notifier> {
+    let webhook_url = destination.url.clone();
+    match destination.kind {
+        DestinationKind::Slack => Box::new(SlackNotifier { webhook_url }),
+        DestinationKind::Discord => Box::new(DiscordNotifier { webhook_url }),
+        DestinationKind::Json => Box::new(JsonNotifier { webhook_url }),
+    }
+}
+
+pub fn deliver(notifier: &dyn Notifier, diff: &ItemDiff, sent: &mut usize) -> Result<()> {
+    let result = notifier.notify(diff, sent);
+    WEBHOOK_SENDS
+        .with_label_values(&[
+            notifier.kind(),
+            if result.is_ok() { "success" } else { "failure" },
+        ])
+        .inc();
+
+    match &result {
+        Ok(()) => info!("Delivered notifications via {}", notifier.kind()),
+        Err(err) => error!(
+            "Failed to deliver notifications via {}: {err:?}",
+            notifier.kind()
+        ),
+    }
+    result
+}
@@ -0,0 +1,156 @@
+use std::collections::HashMap;
+
+use crate::config::CONFIG;
+use crate::diff::ItemDiff;
+use crate::notifier::{build_notifier, deliver};
+use crate::storage::CDN_CACHE_DB;
+use color_eyre::{Result, eyre::eyre};
+use log::{error, info, warn};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use sled::Tree;
+
+const OUTBOX_TREE: &str = "outbox";
+const BASE_RETRY_DELAY_SECS: i64 = 30;
+const MAX_RETRY_DELAY_SECS: i64 = 60 * 60;
+// at the max delay, this is roughly two days of retrying
+const MAX_ATTEMPTS: u32 = 60;
+
+#[derive(Serialize, Deserialize)]
+struct OutboxEntry {
+    diff: ItemDiff,
+    created_at: i64,
+    // destinations (as `kind:url`) that haven't had this diff delivered yet
+    pending: Vec<String>,
+    // how many of a multi-message diff's messages each pending destination has already had
+    #[serde(default)]
+    sent_messages: HashMap<String, usize>,
+    attempts: u32,
+    next_attempt_at: i64,
+}
+
+static OUTBOX: Lazy<Tree> = Lazy::new(|| CDN_CACHE_DB.open_tree(OUTBOX_TREE).unwrap());
+
+fn destination_kind(destination: &str) -> &str {
+    // the url part is a webhook secret, so keep it out of the logs
+    destination.split(':').next().unwrap_or_default()
+}
+
+fn retry_delay_secs(attempts: u32) -> i64 {
+    BASE_RETRY_DELAY_SECS
+        .saturating_mul(1 << attempts.min(16))
+        .min(MAX_RETRY_DELAY_SECS)
+}
+
+pub fn enqueue(diff: &ItemDiff) -> Result<()> {
+    let destinations = CONFIG.destinations();
+    if destinations.is_empty() {
+        warn!("No notifiers configured, not queueing notifications");
+        return Ok(());
+    }
+
+    // if we crashed after queueing but before writing the snapshot, this run will see the same diff again
+    for value in OUTBOX.iter().values() {
+        let entry: OutboxEntry = serde_json::from_slice(&value?)?;
+        if entry.diff == *diff {
+            info!("Diff is already queued, not queueing it again");
+            return Ok(());
+        }
+    }
+
+    let id = CDN_CACHE_DB.generate_id()?;
+    let now = time_format::now()?;
+    let entry = OutboxEntry {
+        diff: diff.clone(),
+        created_at: now,
+        pending: destinations.iter().map(ToString::to_string).collect(),
+        sent_messages: HashMap::new(),
+        attempts: 0,
+        next_attempt_at: now,
+    };
+
+    OUTBOX.insert(id.to_be_bytes(), serde_json::to_vec(&entry)?)?;
+    OUTBOX.flush()?;
+    info!("Queued notifications as outbox entry {id}");
+    Ok(())
+}
+
+// entries are removed once every destination has received them. only fails if a delivery
+// attempted this time failed - entries still waiting out their backoff are fine
+pub fn deliver_pending() -> Result<()> {
+    let now = time_format::now()?;
+    let destinations: HashMap<_, _> = CONFIG
+        .destinations()
+        .into_iter()
+        .map(|d| (d.to_string(), d))
+        .collect();
+    let mut failed = 0;
+
+    for kv in OUTBOX.iter() {
+        let (key, value) = kv?;
+        let id = u64::from_be_bytes(key.as_ref().try_into()?);
+        let mut entry: OutboxEntry = serde_json::from_slice(&value)?;
+        if entry.next_attempt_at > now {
+            continue;
+        }
+
+        let OutboxEntry {
+            diff,
+            pending,
+            sent_messages,
+            ..
+        } = &mut entry;
+        pending.retain(|destination| {
+            let Some(config) = destinations.get(destination) else {
+                warn!(
+                    "A {} notifier for outbox entry {id} is no longer configured, skipping it",
+                    destination_kind(destination)
+                );
+                sent_messages.remove(destination);
+                return false;
+            };
+            let sent = sent_messages.entry(destination.clone()).or_default();
+            let failed = deliver(build_notifier(config).as_ref(), diff, sent).is_err();
+            if !failed {
+                sent_messages.remove(destination);
+            }
+            failed
+        });
+
+        if entry.pending.is_empty() {
+            info!("Outbox entry {id} fully delivered");
+            OUTBOX.remove(key)?;
+            continue;
+        }
+
+        failed += 1;
+        entry.attempts += 1;
+        if entry.attempts >= MAX_ATTEMPTS {
+            error!(
+                "Giving up on outbox entry {id} after {} attempts, {} destination(s) never received it",
+                entry.attempts,
+                entry.pending.len()
+            );
+            OUTBOX.remove(key)?;
+            continue;
+        }
+
+        entry.next_attempt_at = now + retry_delay_secs(entry.attempts);
+        warn!(
+            "Outbox entry {id} still pending for {} destination(s), retrying in {}s",
+            entry.pending.len(),
+            entry.next_attempt_at - now
+        );
+        OUTBOX.insert(key, serde_json::to_vec(&entry)?)?;
+    }
+
+    OUTBOX.flush()?;
+
+    if failed > 0 {
+        Err(eyre!(
+            "{failed} outbox entries failed to deliver and are waiting to be retried"
+        ))
+    } else {
+        Ok(())
+    }
+}
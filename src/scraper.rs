@@ -1,18 +1,23 @@
 use std::collections::HashMap;
 use std::hash::Hash;
+use std::sync::RwLock;
 
+use crate::alerts;
+use crate::archive::archived_image;
 use crate::config::CONFIG;
+use crate::http;
+use crate::metrics::{
+    PARSE_FAILURES, REGION_DISCOVERY_FALLBACKS, REGION_ITEMS, REGION_SCRAPE_DURATION,
+};
 use crate::storage::{CDN_CACHE_DB, upload_to_cdn};
 use color_eyre::{Result, eyre::eyre};
-use log::debug;
+use log::{debug, warn};
 use once_cell::sync::Lazy;
 use rayon::prelude::*;
-use reqwest::blocking::Client;
+use reqwest::blocking::{Client, Response};
 use reqwest::{StatusCode, Url, header, redirect};
 use scraper::{ElementRef, Html, Selector};
 use serde::{Deserialize, Serialize};
-use strum::VariantArray;
-use strum_macros::{Display, VariantArray};
 
 pub static CLIENT: Lazy<Client> = Lazy::new(|| {
     let mut headers = header::HeaderMap::new();
@@ -31,47 +36,155 @@ pub static CLIENT: Lazy<Client> = Lazy::new(|| {
         .expect("failed to build scraping client")
 });
 
-#[derive(Display, Debug, VariantArray, Clone, Hash, PartialEq, Eq, Serialize, Deserialize)]
-pub enum Region {
-    #[strum(to_string = "United States")]
-    UnitedStates,
-    #[strum(to_string = "EU")]
-    Europe,
-    #[strum(to_string = "United Kingdom")]
-    UnitedKingdom,
-    #[strum(to_string = "India")]
-    India,
-    #[strum(to_string = "Canada")]
-    Canada,
-    #[strum(to_string = "Australia")]
-    Australia,
-    #[strum(to_string = "Rest of World")]
-    Global,
-}
+// the regions flavortown had when we hardcoded them: (code, dropdown name, the enum name older snapshots used)
+const KNOWN_REGIONS: &[(&str, &str, &str)] = &[
+    ("US", "United States", "UnitedStates"),
+    ("EU", "EU", "Europe"),
+    ("UK", "United Kingdom", "UnitedKingdom"),
+    ("IN", "India", "India"),
+    ("CA", "Canada", "Canada"),
+    ("AU", "Australia", "Australia"),
+    ("XX", "Rest of World", "Global"),
+];
+
+// regions (and their dropdown names) as of the last scrape, or the known ones before we've scraped
+static REGIONS: Lazy<RwLock<Vec<(Region, String)>>> = Lazy::new(|| {
+    RwLock::new(
+        KNOWN_REGIONS
+            .iter()
+            .map(|(code, name, _)| (Region::new(code), (*name).to_string()))
+            .collect(),
+    )
+});
+
+// a region code as flavortown's region dropdown has it, e.g. "US"
+#[derive(Debug, Clone, Hash, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(into = "String", from = "String")]
+pub struct Region(String);
 
 impl Region {
-    pub const fn code(&self) -> &'static str {
-        match self {
-            Self::UnitedStates => "US",
-            Self::Europe => "EU",
-            Self::UnitedKingdom => "UK",
-            Self::India => "IN",
-            Self::Canada => "CA",
-            Self::Australia => "AU",
-            Self::Global => "XX",
-        }
+    pub fn new(code: &str) -> Self {
+        Self(code.trim().to_uppercase())
+    }
+
+    pub fn code(&self) -> &str {
+        &self.0
+    }
+
+    pub fn from_code(code: &str) -> Option<Self> {
+        let code = code.trim();
+        (!code.is_empty() && code.chars().all(|c| c.is_ascii_alphanumeric()))
+            .then(|| Self::new(code))
+    }
+
+    // every region flavortown currently offers, in dropdown order
+    pub fn all() -> Vec<Self> {
+        REGIONS
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(region, _)| region.clone())
+            .collect()
+    }
+
+    pub fn set_all(regions: &[(Self, String)]) {
+        *REGIONS.write().unwrap() = regions.to_vec();
+    }
+
+    // the dropdown's name for the region, falling back to the code for regions that have gone away
+    pub fn name(&self) -> String {
+        REGIONS
+            .read()
+            .unwrap()
+            .iter()
+            .find(|(region, _)| region == self)
+            .map(|(_, name)| name.as_str())
+            .or_else(|| {
+                KNOWN_REGIONS
+                    .iter()
+                    .find(|(code, _, _)| *code == self.code())
+                    .map(|(_, name, _)| *name)
+            })
+            .unwrap_or(self.code())
+            .to_string()
+    }
+}
+
+impl From<String> for Region {
+    fn from(value: String) -> Self {
+        KNOWN_REGIONS
+            .iter()
+            .find(|(_, _, legacy)| *legacy == value)
+            .map_or_else(|| Self::new(&value), |(code, _, _)| Self::new(code))
+    }
+}
+
+impl From<Region> for String {
+    fn from(region: Region) -> Self {
+        region.0
+    }
+}
+
+impl std::fmt::Display for Region {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
     }
 }
 
 pub type ShopItems = Vec<ShopItem>;
 pub type ShopItemId = usize;
 
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, Eq, PartialEq)]
+pub enum Stock {
+    #[default]
+    Unlimited,
+    Limited(u32),
+    SoldOut,
+}
+
+impl Stock {
+    // "5 left", "Only 1 remaining!", "Sold out" - anything without a number is unlimited
+    fn parse(text: &str) -> Self {
+        if text.to_lowercase().contains("sold out") {
+            return Self::SoldOut;
+        }
+
+        // only the first number - "5 left of 10" is 5, not 510 ("1,000 left" is still 1000)
+        let digits = text
+            .chars()
+            .skip_while(|c| !c.is_ascii_digit())
+            .take_while(|c| c.is_ascii_digit() || *c == ',')
+            .filter(|c| c.is_ascii_digit())
+            .collect::<String>();
+        match digits.parse() {
+            Ok(0) => Self::SoldOut,
+            Ok(remaining) => Self::Limited(remaining),
+            Err(_) => Self::Unlimited,
+        }
+    }
+}
+
+impl std::fmt::Display for Stock {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Unlimited => write!(f, "Unlimited"),
+            Self::Limited(remaining) => write!(f, "{remaining} left"),
+            Self::SoldOut => write!(f, "Sold out"),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
 pub struct ShopItem {
     pub title: String,
     pub description: String,
     pub prices: HashMap<Region, u32>,
     pub image_url: Url,
+    // the image's content hash - blob ids and mirror urls can change while the picture doesn't
+    #[serde(default)]
+    pub image_sha256: Option<String>,
+    #[serde(default)]
+    pub stock: Stock,
 
     pub image_id: usize,
     pub id: ShopItemId,
@@ -83,6 +196,23 @@ impl ShopItem {
         url.set_query(Some(format!("shop_item_id={}", self.id).as_str()));
         url
     }
+
+    // old snapshots (and dry runs) don't have hashes, so fall back to comparing urls
+    pub fn image_changed(&self, other: &Self) -> bool {
+        match (&self.image_sha256, &other.image_sha256) {
+            (Some(old), Some(new)) => old != new,
+            _ => self.image_url != other.image_url,
+        }
+    }
+
+    // whether anything worth telling people about changed
+    pub fn changed_from(&self, old: &Self) -> bool {
+        self.title != old.title
+            || self.description != old.description
+            || self.prices != old.prices
+            || self.stock != old.stock
+            || self.image_changed(old)
+    }
 }
 
 fn select_one<'a>(element: &'a ElementRef, selector: &str) -> Result<ElementRef<'a>> {
@@ -94,7 +224,11 @@ fn select_one<'a>(element: &'a ElementRef, selector: &str) -> Result<ElementRef<
 
 fn parse_shop_item(element: ElementRef, region: &Region) -> Result<ShopItem> {
     let title = select_one(&element, "h4")?.inner_html();
-    let description = select_one(&element, "div.shop-item-card__description > p")?.inner_html();
+    // the description is only shown in notifications, so a card without one is still worth
+    // tracking - otherwise its price and stock would look deleted
+    let description = select_one(&element, "div.shop-item-card__description > p")
+        .map(|e| e.inner_html())
+        .unwrap_or_default();
     let price: u32 = select_one(&element, "span.shop-item-card__price")?
         .text()
         .collect::<String>()
@@ -111,6 +245,20 @@ fn parse_shop_item(element: ElementRef, region: &Region) -> Result<ShopItem> {
         .attr("data-shop-id")
         .ok_or_else(|| eyre!("missing item id"))?
         .parse()?;
+    // the `shop-item-card--sold-out` class and `.shop-item-card__stock` element are guesses that
+    // haven't been checked against a recorded page yet - if they're wrong every item just reads
+    // as unlimited stock
+    let stock = if element
+        .value()
+        .classes()
+        .any(|c| c == "shop-item-card--sold-out")
+    {
+        Stock::SoldOut
+    } else {
+        select_one(&element, ".shop-item-card__stock")
+            .map(|e| Stock::parse(&e.text().collect::<String>()))
+            .unwrap_or_default()
+    };
 
     let mut prices = HashMap::new();
     prices.insert(region.clone(), price);
@@ -120,71 +268,405 @@ fn parse_shop_item(element: ElementRef, region: &Region) -> Result<ShopItem> {
         description,
         id,
         image_url,
+        image_sha256: None,
         image_id,
+        stock,
         prices,
     })
 }
 
+#[derive(Debug)]
+pub enum ScrapeError {
+    // flavortown bounces you to the login page once the session cookie expires
+    LoginRequired { location: Option<String> },
+    MissingCsrfToken,
+    UnexpectedStatus { url: Url, status: StatusCode },
+    // the page we got back is for a different region than the one we just switched to
+    RegionMismatch { expected: Region, found: String },
+    Parse(String),
+}
+
+impl ScrapeError {
+    // problems that only spoil one region's page, and might not happen again if we ask twice
+    pub const fn is_region_specific(&self) -> bool {
+        matches!(
+            self,
+            Self::UnexpectedStatus { .. } | Self::RegionMismatch { .. } | Self::Parse(_)
+        )
+    }
+}
+
+impl std::fmt::Display for ScrapeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::LoginRequired {
+                location: Some(location),
+            } => write!(f, "session expired, redirected to {location}"),
+            Self::LoginRequired { location: None } => {
+                write!(f, "session expired, got a sign-in page")
+            }
+            Self::MissingCsrfToken => write!(f, "couldn't find the csrf token on the shop page"),
+            Self::UnexpectedStatus { url, status } => write!(f, "{url} returned {status}"),
+            Self::RegionMismatch { expected, found } => write!(
+                f,
+                "switched to {expected} ({}) but the shop page is for {found:?}",
+                expected.code()
+            ),
+            Self::Parse(message) => write!(f, "couldn't parse the shop page: {message}"),
+        }
+    }
+}
+
+impl std::error::Error for ScrapeError {}
+
+fn ensure_logged_in(res: &Response) -> Result<(), ScrapeError> {
+    let status = res.status();
+    if status.is_redirection() || status == StatusCode::UNAUTHORIZED {
+        let location = res
+            .headers()
+            .get(header::LOCATION)
+            .and_then(|l| l.to_str().ok())
+            .map(String::from);
+        return Err(ScrapeError::LoginRequired { location });
+    }
+    Ok(())
+}
+
+fn ensure_ok(res: &Response) -> Result<(), ScrapeError> {
+    if res.status() != StatusCode::OK {
+        return Err(ScrapeError::UnexpectedStatus {
+            url: res.url().clone(),
+            status: res.status(),
+        });
+    }
+    Ok(())
+}
+
+fn looks_like_login_page(html: &str) -> bool {
+    let document = Html::parse_document(html);
+    let has_any = |selector: &str| {
+        document
+            .select(&Selector::parse(selector).unwrap())
+            .next()
+            .is_some()
+    };
+    !has_any("button.dropdown__button")
+        && has_any(r#"form[action*="sign_in"], form[action*="login"], a[href*="/auth/"]"#)
+}
+
 fn fetch_shop_page() -> Result<String> {
-    let res = CLIENT
-        .get(CONFIG.base_url.join("shop")?)
-        .send()?
-        .error_for_status()?;
-    assert_eq!(res.status(), StatusCode::OK);
-    res.text().map_err(Into::into)
+    let res = http::send(CLIENT.get(CONFIG.base_url.join("shop")?))?;
+    ensure_logged_in(&res)?;
+    ensure_ok(&res)?;
+
+    let html = res.text()?;
+    if looks_like_login_page(&html) {
+        return Err(ScrapeError::LoginRequired { location: None }.into());
+    }
+    Ok(html)
 }
 
-fn get_csrf_token() -> Result<String> {
-    let document = Html::parse_document(&fetch_shop_page()?);
+fn get_csrf_token(document: &Html) -> Result<String, ScrapeError> {
     document
         .select(&Selector::parse("meta[name=\"csrf-token\"]").unwrap())
         .next()
         .and_then(|e| e.attr("content"))
         .map(String::from)
-        .ok_or_else(|| eyre!("Failed to find csrf-token"))
+        .ok_or(ScrapeError::MissingCsrfToken)
+}
+
+// expects the region dropdown to list every region as `<button data-region="US">United States</button>`.
+// that hasn't been checked against a recorded page yet - if it's wrong this finds nothing, and
+// `scrape_shop` falls back to the known regions and says so
+fn parse_regions(document: &Html) -> Vec<(Region, String)> {
+    let mut regions: Vec<(Region, String)> = Vec::new();
+    for element in document.select(&Selector::parse(".dropdown [data-region]").unwrap()) {
+        let Some(region) = element.attr("data-region").and_then(Region::from_code) else {
+            continue;
+        };
+        if regions.iter().any(|(r, _)| *r == region) {
+            continue;
+        }
+        let name = element.text().collect::<String>().trim().to_string();
+        regions.push((region, name));
+    }
+    regions
 }
 
 fn set_region(region: &Region, csrf_token: &str) -> Result<()> {
-    let res = CLIENT
-        .patch(CONFIG.base_url.join("shop/update_region")?)
-        .header("X-CSRF-Token", csrf_token)
-        .form(&[("region", region.code())])
-        .send()?
-        .error_for_status()?;
-    assert_eq!(res.status(), StatusCode::OK);
+    let res = http::send(
+        CLIENT
+            .patch(CONFIG.base_url.join("shop/update_region")?)
+            .header("X-CSRF-Token", csrf_token)
+            .form(&[("region", region.code())]),
+    )?;
+    ensure_logged_in(&res)?;
+    ensure_ok(&res)?;
     Ok(())
 }
 
-fn scrape_region(region: &Region, csrf_token: &str) -> Result<ShopItems> {
-    set_region(region, csrf_token)?;
+#[derive(Debug, Clone, Serialize)]
+pub struct ParseWarning {
+    pub region: Region,
+    // `None` if the card didn't even have a usable `data-shop-id`
+    pub shop_id: Option<ShopItemId>,
+    pub error: String,
+}
+
+#[derive(Debug, Default)]
+pub struct ScrapeResult {
+    pub items: ShopItems,
+    pub parse_warnings: Vec<ParseWarning>,
+    // every region in the dropdown, with its display name
+    pub regions: Vec<(Region, String)>,
+    // false if the dropdown couldn't be read and `regions` are just the ones we already knew
+    pub regions_discovered: bool,
+    // regions whose page we couldn't scrape, so their prices are unknown rather than gone
+    pub skipped_regions: Vec<Region>,
+}
 
-    let document = Html::parse_document(&fetch_shop_page()?);
+fn parse_shop_page(html: &str, region: &Region) -> Result<ScrapeResult> {
+    let document = Html::parse_document(html);
     let root = document.root_element();
 
     // step 1: region selection
     let selected_region = select_one(
         &root,
         "button.dropdown__button > span.dropdown__selected > span.dropdown__char-span",
-    )?
+    )
+    .map_err(|err| ScrapeError::Parse(err.to_string()))?
     .text()
-    .next()
-    .unwrap();
-    assert_eq!(selected_region, region.to_string());
+    .collect::<String>();
+    if selected_region.trim() != region.name() {
+        return Err(ScrapeError::RegionMismatch {
+            expected: region.clone(),
+            found: selected_region.trim().to_string(),
+        }
+        .into());
+    }
 
-    // step 2: parse all shop items
-    document
-        .select(&Selector::parse(".shop-item-card").unwrap())
-        .map(|element_ref| parse_shop_item(element_ref, region))
-        .collect()
+    // step 2: parse all shop items - one bad card shouldn't take the rest down with it
+    let mut result = ScrapeResult::default();
+    for element_ref in document.select(&Selector::parse(".shop-item-card").unwrap()) {
+        match parse_shop_item(element_ref, region) {
+            Ok(item) => result.items.push(item),
+            Err(err) => {
+                PARSE_FAILURES.inc();
+                let shop_id = element_ref
+                    .attr("data-shop-id")
+                    .and_then(|id| id.parse().ok());
+                warn!("Failed to parse item {shop_id:?} in {region}: {err}");
+                result.parse_warnings.push(ParseWarning {
+                    region: region.clone(),
+                    shop_id,
+                    error: err.to_string(),
+                });
+            }
+        }
+    }
+    Ok(result)
+}
+
+// checked per region against that region's own cards - a broken card shows up once in
+// every region, but `items` only has it once
+fn check_parse_failures(result: &ScrapeResult, max_failure_percent: u32) -> Result<()> {
+    let mut failed_by_region: HashMap<&Region, usize> = HashMap::new();
+    for warning in &result.parse_warnings {
+        *failed_by_region.entry(&warning.region).or_default() += 1;
+    }
+
+    for (region, failed) in failed_by_region {
+        let parsed = result
+            .items
+            .iter()
+            .filter(|item| item.prices.contains_key(region))
+            .count();
+        let total = failed + parsed;
+        if failed * 100 > total * max_failure_percent as usize {
+            return Err(ScrapeError::Parse(format!(
+                "{failed} of {total} shop items in {region} failed to parse, which is over the {max_failure_percent}% threshold"
+            ))
+            .into());
+        }
+    }
+    Ok(())
+}
+
+// items we couldn't parse this time keep whatever we knew about them, rather than looking deleted
+pub fn carry_forward_unparsed(
+    items: &mut ShopItems,
+    old_items: &ShopItems,
+    warnings: &[ParseWarning],
+) {
+    for warning in warnings {
+        let Some(id) = warning.shop_id else {
+            continue;
+        };
+        let Some(old_item) = old_items.iter().find(|item| item.id == id) else {
+            continue;
+        };
+
+        match items.iter_mut().find(|item| item.id == id) {
+            Some(item) => {
+                if let Some(price) = old_item.prices.get(&warning.region) {
+                    item.prices.entry(warning.region.clone()).or_insert(*price);
+                }
+            }
+            None => items.push(old_item.clone()),
+        }
+    }
+    items.sort_by_key(|item| item.id);
+}
+
+// regions that lost more than `max_drop_percent` of their items since the last snapshot -
+// that's far more likely to be a broken page than flavortown emptying the shop. regions with
+// fewer than `min_old_items` are left alone, one item going is a big percentage of a small region
+pub fn collapsed_regions(
+    items: &ShopItems,
+    old_items: &ShopItems,
+    max_drop_percent: u32,
+    min_old_items: usize,
+) -> Vec<Region> {
+    let count_by_region = |items: &ShopItems| {
+        let mut counts: HashMap<Region, usize> = HashMap::new();
+        for region in items.iter().flat_map(|item| item.prices.keys()) {
+            *counts.entry(region.clone()).or_default() += 1;
+        }
+        counts
+    };
+    let old_counts = count_by_region(old_items);
+    let new_counts = count_by_region(items);
+
+    let mut collapsed: Vec<Region> = old_counts
+        .into_iter()
+        .filter(|(_, old_count)| *old_count >= min_old_items)
+        .filter(|(region, old_count)| {
+            let new_count = new_counts.get(region).copied().unwrap_or_default();
+            new_count * 100 < old_count * (100 - max_drop_percent.min(100) as usize)
+        })
+        .map(|(region, _)| region)
+        .collect();
+    collapsed.sort();
+    collapsed
+}
+
+// swaps whatever we scraped for these regions back to the last snapshot's prices, so a
+// region we couldn't scrape (or that came back broken) doesn't look like mass removal
+pub fn carry_forward_regions(items: &mut ShopItems, old_items: &ShopItems, regions: &[Region]) {
+    if regions.is_empty() {
+        return;
+    }
+
+    for item in items.iter_mut() {
+        item.prices.retain(|region, _| !regions.contains(region));
+    }
+    items.retain(|item| !item.prices.is_empty());
+
+    for old_item in old_items {
+        let old_prices: HashMap<Region, u32> = old_item
+            .prices
+            .iter()
+            .filter(|(region, _)| regions.contains(region))
+            .map(|(region, price)| (region.clone(), *price))
+            .collect();
+        if old_prices.is_empty() {
+            continue;
+        }
+
+        match items.iter_mut().find(|item| item.id == old_item.id) {
+            Some(item) => item.prices.extend(old_prices),
+            None => items.push(ShopItem {
+                prices: old_prices,
+                ..old_item.clone()
+            }),
+        }
+    }
+    items.sort_by_key(|item| item.id);
+}
+
+fn scrape_region(region: &Region, csrf_token: &str) -> Result<ScrapeResult> {
+    let _timer = REGION_SCRAPE_DURATION
+        .with_label_values(&[region.code()])
+        .start_timer();
+    set_region(region, csrf_token)?;
+
+    let result = parse_shop_page(&fetch_shop_page()?, region)?;
+    REGION_ITEMS
+        .with_label_values(&[region.code()])
+        .set(result.items.len() as i64);
+    Ok(result)
 }
 
-pub fn scrape() -> Result<Vec<ShopItem>> {
+fn is_region_specific(err: &color_eyre::Report) -> bool {
+    err.downcast_ref::<ScrapeError>()
+        .is_some_and(ScrapeError::is_region_specific)
+}
+
+// a region that comes back wrong gets one more try before we give up on it for this run
+fn scrape_region_with_retry(region: &Region, csrf_token: &str) -> Result<ScrapeResult> {
+    match scrape_region(region, csrf_token) {
+        Err(err) if is_region_specific(&err) => {
+            warn!("Scraping {region} failed ({err}), trying once more");
+            scrape_region(region, csrf_token)
+        }
+        result => result,
+    }
+}
+
+// the raw shop page for a region, with the csrf token scrubbed so it's safe to commit as a test fixture
+pub fn fetch_fixture_page(region: &Region) -> Result<String> {
+    let csrf_token = get_csrf_token(&Html::parse_document(&fetch_shop_page()?))?;
+    set_region(region, &csrf_token)?;
+    Ok(fetch_shop_page()?.replace(&csrf_token, "REDACTED"))
+}
+
+// scrapes every region without touching the CDN, so items still point at flavortown's own image urls
+pub fn scrape_shop() -> Result<ScrapeResult> {
     let mut items: HashMap<ShopItemId, ShopItem> = HashMap::new();
-    let csrf_token = get_csrf_token()?;
+    let mut parse_warnings = Vec::new();
+    let mut skipped_regions = Vec::new();
+    let (csrf_token, regions) = {
+        let document = Html::parse_document(&fetch_shop_page()?);
+        (get_csrf_token(&document)?, parse_regions(&document))
+    };
+    let regions_discovered = !regions.is_empty();
+    let regions = if !regions_discovered {
+        warn!(
+            "Couldn't find any regions in the region dropdown, scraping the ones we already know"
+        );
+        REGION_DISCOVERY_FALLBACKS.inc();
+        // what the dropdown last offered, so a restart doesn't forget regions it's added since
+        let last_seen = alerts::last_seen_regions()?;
+        if last_seen.is_empty() {
+            Region::all()
+                .into_iter()
+                .map(|region| {
+                    let name = region.name();
+                    (region, name)
+                })
+                .collect()
+        } else {
+            Region::set_all(&last_seen);
+            last_seen
+        }
+    } else {
+        Region::set_all(&regions);
+        regions
+    };
 
-    for region in Region::VARIANTS {
-        debug!("Now scraping {:?}", region);
-        for item in scrape_region(region, &csrf_token)? {
+    for (region, _) in &regions {
+        debug!("Now scraping {region:?}");
+        let result = match scrape_region_with_retry(region, &csrf_token) {
+            Ok(result) => result,
+            Err(err) if is_region_specific(&err) => {
+                warn!("Skipping {region}, its prices will be carried over: {err}");
+                skipped_regions.push(region.clone());
+                continue;
+            }
+            Err(err) => return Err(err),
+        };
+        parse_warnings.extend(result.parse_warnings);
+        for item in result.items {
             items
                 .entry(item.id)
                 .and_modify(|e| {
@@ -194,16 +676,208 @@ pub fn scrape() -> Result<Vec<ShopItem>> {
         }
     }
 
-    items
+    if skipped_regions.len() == regions.len() {
+        return Err(eyre!("couldn't scrape any region"));
+    }
+
+    let mut items = items.into_values().collect::<ShopItems>();
+    items.sort_by_key(|item| item.id);
+    let result = ScrapeResult {
+        items,
+        parse_warnings,
+        regions,
+        regions_discovered,
+        skipped_regions,
+    };
+    check_parse_failures(&result, CONFIG.max_parse_failure_percent)?;
+    Ok(result)
+}
+
+pub fn scrape() -> Result<ScrapeResult> {
+    let mut result = scrape_shop()?;
+
+    result
+        .items
         .par_iter_mut()
-        .try_for_each(|(_, item)| -> Result<()> {
+        .try_for_each(|item| -> Result<()> {
             item.image_url = upload_to_cdn(item.image_id, &item.image_url.clone())?;
+            item.image_sha256 = archived_image(item.image_id)?.map(|image| image.sha256);
             Ok(())
         })?;
 
     CDN_CACHE_DB.flush()?;
+    Ok(result)
+}
 
-    let mut items = items.into_values().collect::<ShopItems>();
-    items.sort_by_key(|item| item.id);
-    Ok(items)
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // these are hand-written - record real ones from the live site with `flavortown_tracker record-fixture`
+    fn fixture(name: &str) -> String {
+        let path = format!("{}/tests/fixtures/shop/{name}", env!("CARGO_MANIFEST_DIR"));
+        std::fs::read_to_string(&path).unwrap_or_else(|e| panic!("can't read {path}: {e}"))
+    }
+
+    fn find(items: &ShopItems, id: ShopItemId) -> &ShopItem {
+        items.iter().find(|item| item.id == id).unwrap()
+    }
+
+    #[test]
+    fn parses_us_page() {
+        let items = parse_shop_page(&fixture("us.html"), &Region::new("US"))
+            .unwrap()
+            .items;
+        assert_eq!(items.len(), 3);
+
+        let pi = find(&items, 1);
+        assert_eq!(pi.title, "Raspberry Pi 5");
+        assert_eq!(pi.description, "A tiny computer.");
+        assert_eq!(pi.prices, HashMap::from([(Region::new("US"), 250)]));
+        assert_eq!(pi.image_id, 101);
+        assert_eq!(
+            pi.image_url.path_segments().unwrap().next_back(),
+            Some("pi5.png")
+        );
+        assert_eq!(pi.stock, Stock::Unlimited);
+
+        assert_eq!(find(&items, 2).stock, Stock::Limited(3));
+        assert_eq!(find(&items, 3).stock, Stock::SoldOut);
+    }
+
+    #[test]
+    fn handles_odd_prices_and_missing_descriptions() {
+        let result = parse_shop_page(&fixture("uk.html"), &Region::new("UK")).unwrap();
+        assert!(result.parse_warnings.is_empty());
+
+        assert_eq!(
+            find(&result.items, 1).prices,
+            HashMap::from([(Region::new("UK"), 1200)])
+        );
+
+        let mystery = find(&result.items, 4);
+        assert_eq!(mystery.description, "");
+        assert_eq!(mystery.prices, HashMap::from([(Region::new("UK"), 30)]));
+        assert_eq!(mystery.image_id, 201);
+    }
+
+    #[test]
+    fn skips_items_that_fail_to_parse() {
+        let result = parse_shop_page(&fixture("missing_image.html"), &Region::new("US")).unwrap();
+
+        assert_eq!(result.items.len(), 1);
+        assert_eq!(result.items[0].id, 1);
+
+        let [warning] = result.parse_warnings.as_slice() else {
+            panic!("expected exactly one parse warning");
+        };
+        assert_eq!(warning.shop_id, Some(5));
+        assert!(warning.error.contains("shop-item-card__image"));
+
+        assert!(check_parse_failures(&result, 50).is_ok());
+        assert!(check_parse_failures(&result, 10).is_err());
+    }
+
+    #[test]
+    fn counts_a_bad_card_once_per_region() {
+        let regions: Vec<Region> = KNOWN_REGIONS
+            .iter()
+            .map(|(code, _, _)| Region::new(code))
+            .collect();
+        let mut item = parse_shop_page(&fixture("us.html"), &Region::new("US"))
+            .unwrap()
+            .items
+            .remove(0);
+        item.prices = regions.iter().map(|region| (region.clone(), 10)).collect();
+        let result = ScrapeResult {
+            items: (0..15).map(|id| ShopItem { id, ..item.clone() }).collect(),
+            parse_warnings: regions
+                .iter()
+                .map(|region| ParseWarning {
+                    region: region.clone(),
+                    shop_id: Some(99),
+                    error: "missing element: h4".into(),
+                })
+                .collect(),
+            ..Default::default()
+        };
+
+        // 1 of 16 cards in each region, not 7 of 22
+        assert!(check_parse_failures(&result, 10).is_ok());
+        assert!(check_parse_failures(&result, 5).is_err());
+    }
+
+    #[test]
+    fn rejects_page_for_the_wrong_region() {
+        let err = parse_shop_page(&fixture("wrong_region.html"), &Region::new("US")).unwrap_err();
+        match err.downcast_ref::<ScrapeError>() {
+            Some(ScrapeError::RegionMismatch { expected, found }) => {
+                assert_eq!(*expected, Region::new("US"));
+                assert_eq!(found, "EU");
+            }
+            other => panic!("expected a region mismatch, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn detects_login_page() {
+        assert!(looks_like_login_page(&fixture("login.html")));
+        assert!(!looks_like_login_page(&fixture("us.html")));
+    }
+
+    #[test]
+    fn discovers_regions_from_the_dropdown() {
+        let regions = parse_regions(&Html::parse_document(&fixture("us.html")));
+        let codes: Vec<_> = regions.iter().map(|(r, _)| r.code()).collect();
+        assert_eq!(codes, ["US", "EU", "UK", "JP"]);
+        assert_eq!(regions[3].1, "Japan");
+    }
+
+    #[test]
+    fn reads_regions_from_old_snapshots() {
+        let prices: HashMap<Region, u32> =
+            serde_json::from_str(r#"{"UnitedKingdom": 12, "Global": 3, "JP": 7}"#).unwrap();
+        assert_eq!(prices[&Region::new("UK")], 12);
+        assert_eq!(prices[&Region::new("XX")], 3);
+        assert_eq!(prices[&Region::new("JP")], 7);
+        assert_eq!(
+            serde_json::to_string(&HashMap::from([(Region::new("UK"), 12)])).unwrap(),
+            r#"{"UK":12}"#
+        );
+    }
+
+    #[test]
+    fn carries_forward_a_collapsed_region() {
+        let old = parse_shop_page(&fixture("us.html"), &Region::new("US"))
+            .unwrap()
+            .items;
+        let mut items = old.clone();
+        for item in &mut items {
+            item.prices = HashMap::from([(Region::new("UK"), 10)]);
+        }
+        items.truncate(2);
+
+        assert_eq!(collapsed_regions(&items, &old, 50, 3), [Region::new("US")]);
+        assert!(collapsed_regions(&items, &old, 100, 3).is_empty());
+        assert!(collapsed_regions(&items, &old, 50, 4).is_empty());
+
+        carry_forward_regions(&mut items, &old, &[Region::new("US")]);
+        assert_eq!(items.len(), 3);
+        assert_eq!(
+            find(&items, 1).prices,
+            HashMap::from([(Region::new("UK"), 10), (Region::new("US"), 250)])
+        );
+        assert_eq!(find(&items, 3).prices.len(), 1);
+    }
+
+    #[test]
+    fn parses_stock_text() {
+        assert_eq!(Stock::parse("5 left"), Stock::Limited(5));
+        assert_eq!(Stock::parse("Only 1 remaining!"), Stock::Limited(1));
+        assert_eq!(Stock::parse("0 left"), Stock::SoldOut);
+        assert_eq!(Stock::parse("5 left of 10"), Stock::Limited(5));
+        assert_eq!(Stock::parse("1,000 left"), Stock::Limited(1000));
+        assert_eq!(Stock::parse("SOLD OUT"), Stock::SoldOut);
+        assert_eq!(Stock::parse("In stock"), Stock::Unlimited);
+    }
 }
@@ -0,0 +1,169 @@
+use std::io::Cursor;
+use std::sync::RwLock;
+use std::thread;
+use std::time::Duration;
+
+use crate::archive::archived_image;
+use crate::config::{CONFIG, MirrorKind};
+use crate::mirror::content_type;
+use crate::scraper::{ParseWarning, Region, ShopItem, ShopItemId};
+use crate::storage::load_latest_snapshot;
+use crate::{history, metrics};
+
+use color_eyre::{Result, eyre::eyre};
+use log::{error, info};
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use tiny_http::{Header, Method, Request, Response, Server};
+
+#[derive(Serialize, Default)]
+pub struct RunStatus {
+    pub last_run_at: Option<String>,
+    pub last_run_duration_ms: Option<u128>,
+    pub last_success_at: Option<String>,
+    pub item_count: Option<usize>,
+    pub last_error: Option<String>,
+    pub parse_warnings: Vec<ParseWarning>,
+    // set when some regions' prices were carried over rather than freshly scraped
+    pub degraded: bool,
+    pub skipped_regions: Vec<Region>,
+    pub collapsed_regions: Vec<Region>,
+}
+
+type HttpResponse = Response<Cursor<Vec<u8>>>;
+
+static STATUS: Lazy<RwLock<RunStatus>> = Lazy::new(Default::default);
+
+pub fn record_run(duration: Duration, result: &Result<usize>) {
+    let ts = time_format::now().unwrap();
+    let now = time_format::strftime_utc("%Y-%m-%dT%H:%M:%SZ", ts).unwrap();
+
+    let mut status = STATUS.write().unwrap();
+    status.last_run_at = Some(now.clone());
+    status.last_run_duration_ms = Some(duration.as_millis());
+    match result {
+        Ok(item_count) => {
+            status.last_success_at = Some(now);
+            status.item_count = Some(*item_count);
+            status.last_error = None;
+        }
+        Err(err) => status.last_error = Some(format!("{err:#}")),
+    }
+}
+
+pub fn record_scrape_issues(
+    warnings: &[ParseWarning],
+    skipped_regions: &[Region],
+    collapsed_regions: &[Region],
+) {
+    let mut status = STATUS.write().unwrap();
+    status.parse_warnings = warnings.to_vec();
+    status.degraded = !skipped_regions.is_empty() || !collapsed_regions.is_empty();
+    status.skipped_regions = skipped_regions.to_vec();
+    status.collapsed_regions = collapsed_regions.to_vec();
+}
+
+fn json_response<T: Serialize>(value: &T, status_code: u16) -> Result<HttpResponse> {
+    Ok(Response::from_data(serde_json::to_vec(value)?)
+        .with_status_code(status_code)
+        .with_header(Header::from_bytes("Content-Type", "application/json").unwrap()))
+}
+
+fn not_found() -> Result<HttpResponse> {
+    json_response(&serde_json::json!({ "error": "not found" }), 404)
+}
+
+fn find_item(id: &str) -> Result<Option<ShopItem>> {
+    let Ok(id) = id.parse::<ShopItemId>() else {
+        return Ok(None);
+    };
+    Ok(load_latest_snapshot()?.and_then(|items| items.into_iter().find(|item| item.id == id)))
+}
+
+fn query_param<'a>(url: &'a str, name: &str) -> Option<&'a str> {
+    url.split_once('?')?
+        .1
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value)
+}
+
+// images mirrored with IMAGE_MIRROR=local
+fn serve_image(name: &str) -> Result<HttpResponse> {
+    if CONFIG.image_mirror != MirrorKind::Local || name.starts_with('.') {
+        return not_found();
+    }
+    match std::fs::read(CONFIG.image_dir().join(name)) {
+        Ok(file) => Ok(Response::from_data(file)
+            .with_header(Header::from_bytes("Content-Type", content_type(name)).unwrap())),
+        Err(_) => not_found(),
+    }
+}
+
+// our own copy of an item image, by the `image_id` stored in snapshots
+fn serve_archived_image(image_id: &str) -> Result<HttpResponse> {
+    let Ok(image_id) = image_id.parse() else {
+        return not_found();
+    };
+    let Some(archived) = archived_image(image_id)? else {
+        return not_found();
+    };
+    Ok(Response::from_data(archived.read()?).with_header(
+        Header::from_bytes("Content-Type", content_type(&archived.file_name())).unwrap(),
+    ))
+}
+
+fn route(request: &Request) -> Result<HttpResponse> {
+    if request.method() != &Method::Get {
+        return json_response(&serde_json::json!({ "error": "method not allowed" }), 405);
+    }
+
+    let path = request.url().split('?').next().unwrap_or_default();
+    match path
+        .trim_end_matches('/')
+        .split('/')
+        .collect::<Vec<_>>()
+        .as_slice()
+    {
+        ["", "healthz"] => Ok(Response::from_string("ok")),
+        ["", "metrics"] => Ok(Response::from_string(metrics::render()?)
+            .with_header(Header::from_bytes("Content-Type", prometheus::TEXT_FORMAT).unwrap())),
+        ["", "status"] => json_response(&*STATUS.read().unwrap(), 200),
+        ["", "images", name] => serve_image(name),
+        ["", "archive", image_id] => serve_archived_image(image_id),
+        ["", "items"] => json_response(&load_latest_snapshot()?.unwrap_or_default(), 200),
+        ["", "items", id, "history"] => {
+            let Ok(id) = id.parse() else {
+                return not_found();
+            };
+            let region = query_param(request.url(), "region").and_then(Region::from_code);
+            json_response(&history::item_history(id, region.as_ref())?, 200)
+        }
+        ["", "items", id] => match find_item(id)? {
+            Some(item) => json_response(&item, 200),
+            None => not_found(),
+        },
+        _ => not_found(),
+    }
+}
+
+pub fn spawn() -> Result<()> {
+    let server = Server::http(&CONFIG.http_addr)
+        .map_err(|e| eyre!("failed to bind {}: {e}", CONFIG.http_addr))?;
+    info!("HTTP server listening on {}", CONFIG.http_addr);
+
+    thread::spawn(move || {
+        for request in server.incoming_requests() {
+            let response = route(&request).unwrap_or_else(|err| {
+                error!("Failed to handle {}: {err:?}", request.url());
+                json_response(&serde_json::json!({ "error": err.to_string() }), 500).unwrap()
+            });
+            if let Err(err) = request.respond(response) {
+                error!("Failed to send HTTP response: {err}");
+            }
+        }
+    });
+
+    Ok(())
+}
@@ -0,0 +1,256 @@
+use std::collections::HashMap;
+
+use crate::diff::{ItemDiff, format_prices, prices_changed};
+use crate::scraper::{CLIENT, Region, ShopItem, Stock};
+use color_eyre::Result;
+use log::info;
+use reqwest::Url;
+use slack_morphism::prelude::*;
+
+const EMOJI_SHELLS: &str = ":shells:";
+const EMOJI_TROLLEY: &str = ":tw_shopping_trolley:";
+const EMOJI_NEW: &str = ":new:";
+const EMOJI_BACK: &str = ":back:";
+const EMOJI_TRASH: &str = ":win10-trash:";
+const EMOJI_STAR: &str = ":star:";
+const EMOJI_ROBOT: &str = ":robot_face:";
+const EMOJI_WARNING: &str = ":warning:";
+
+const LOW_STOCK_THRESHOLD: u32 = 5;
+// https://docs.slack.dev/reference/block-kit/blocks - messages are capped at 50 blocks
+const MAX_BLOCKS_PER_MESSAGE: usize = 50;
+
+fn escape_markdown(text: &str) -> String {
+    text.chars()
+        .flat_map(|c| match c {
+            '_' | '*' | '~' | '`' => vec!['\\', c],
+            _ => vec![c],
+        })
+        .collect()
+}
+
+fn item_header(emoji: &str, item: &ShopItem, prices: &HashMap<Region, u32>) -> String {
+    format!(
+        "{emoji} {} ({EMOJI_SHELLS} {})",
+        item.title,
+        format_prices(prices)
+    )
+}
+
+fn item_description(desc: &str) -> String {
+    if desc.is_empty() {
+        String::new()
+    } else {
+        format!("_{}_\n", escape_markdown(desc))
+    }
+}
+
+fn format_stock(stock: &Stock) -> String {
+    match stock {
+        Stock::Limited(remaining) if *remaining <= LOW_STOCK_THRESHOLD => {
+            format!("{EMOJI_WARNING} {stock}")
+        }
+        _ => stock.to_string(),
+    }
+}
+
+fn stock_line(old: &Stock, new: &Stock) -> String {
+    if old == new {
+        format!("*Stock:* {}", format_stock(new))
+    } else {
+        format!("*Stock:* {old} → {}", format_stock(new))
+    }
+}
+
+fn buy_button(url: &impl ToString) -> String {
+    format!("<{}|*{EMOJI_TROLLEY} Buy*>", url.to_string())
+}
+
+fn render_new_item(emoji: &str, item: &ShopItem) -> Vec<SlackBlock> {
+    let section_text = format!(
+        "{}{}\n\n{}",
+        item_description(&item.description),
+        stock_line(&item.stock, &item.stock),
+        buy_button(&item.buy_link())
+    );
+
+    vec![
+        SlackHeaderBlock::new(pt!(item_header(emoji, item, &item.prices))).into(),
+        SlackSectionBlock::new().with_text(md!(section_text)).into(),
+        SlackImageBlock::new(
+            item.image_url.clone().into(),
+            format!("Image for {}", item.title),
+        )
+        .into(),
+    ]
+}
+
+fn render_deleted_item(item: &ShopItem) -> Vec<SlackBlock> {
+    vec![
+        SlackHeaderBlock::new(pt!(item_header(EMOJI_TRASH, item, &item.prices))).into(),
+        SlackSectionBlock::new()
+            .with_text(md!(item_description(&item.description)))
+            .into(),
+        SlackImageBlock::new(
+            item.image_url.clone().into(),
+            format!("Image for {}", item.title),
+        )
+        .into(),
+    ]
+}
+
+fn render_updated_item(old: &ShopItem, new: &ShopItem) -> Vec<SlackBlock> {
+    let title = if old.title != new.title {
+        format!("{} → {}", old.title, new.title)
+    } else {
+        new.title.clone()
+    };
+
+    let price = if prices_changed(&old.prices, &new.prices) {
+        format!(
+            "{} → {}",
+            format_prices(&old.prices),
+            format_prices(&new.prices)
+        )
+    } else {
+        format_prices(&new.prices)
+    };
+
+    let description = match (old.description.is_empty(), new.description.is_empty()) {
+        (true, true) => String::new(),
+        (false, false) if old.description == new.description => item_description(&new.description),
+        _ => {
+            let old_desc = if old.description.is_empty() {
+                "_no description_"
+            } else {
+                &escape_markdown(&old.description)
+            };
+            let new_desc = if new.description.is_empty() {
+                "_no description_"
+            } else {
+                &escape_markdown(&new.description)
+            };
+            format!("{old_desc} → {new_desc}\n")
+        }
+    };
+
+    let image_changed = old.image_changed(new);
+    let section_text = format!(
+        "{description}{}{}\n\n{}",
+        stock_line(&old.stock, &new.stock),
+        if image_changed {
+            "\n*Image changed*"
+        } else {
+            ""
+        },
+        buy_button(&new.buy_link())
+    );
+
+    let mut blocks = vec![
+        SlackHeaderBlock::new(pt!(format!("{title} ({EMOJI_SHELLS} {price})"))).into(),
+        SlackSectionBlock::new().with_text(md!(section_text)).into(),
+    ];
+
+    if image_changed {
+        blocks.push(
+            SlackImageBlock::new(
+                old.image_url.clone().into(),
+                format!("Old image for {}", new.title),
+            )
+            .into(),
+        );
+    }
+
+    blocks.push(
+        SlackImageBlock::new(
+            new.image_url.clone().into(),
+            format!("New image for {}", new.title),
+        )
+        .into(),
+    );
+    blocks
+}
+
+fn render_channel_ping() -> Vec<SlackBlock> {
+    vec![SlackContextBlock::new(vec![SlackContextBlockElement::MarkDown(md!(format!(
+        "pinging <!channel> · <https://github.com/skyfallwastaken/flavortown-tracker|{EMOJI_STAR} star the repo!> · <https://hackclub.slack.com/archives/C091UF79VDM|{EMOJI_ROBOT} discord/slackbot ysws>"
+    )))]).into()]
+}
+
+// packs each item's blocks into as few messages as possible, never splitting an item across two
+fn chunk_blocks(groups: Vec<Vec<SlackBlock>>) -> Vec<Vec<SlackBlock>> {
+    let mut chunks: Vec<Vec<SlackBlock>> = Vec::new();
+
+    for group in groups {
+        match chunks.last_mut() {
+            Some(chunk) if chunk.len() + 1 + group.len() <= MAX_BLOCKS_PER_MESSAGE => {
+                chunk.push(SlackDividerBlock::new().into());
+                chunk.extend(group);
+            }
+            _ => chunks.push(group),
+        }
+    }
+
+    chunks
+}
+
+pub fn send_webhook_notifications(
+    webhook_url: &Url,
+    diff: &ItemDiff,
+    sent: &mut usize,
+) -> Result<()> {
+    let mut groups: Vec<Vec<SlackBlock>> = Vec::new();
+
+    for item in &diff.new_items {
+        info!("Sending notification for new item: {}", item.title);
+        groups.push(render_new_item(EMOJI_NEW, item));
+    }
+
+    for item in &diff.returning_items {
+        info!("Sending notification for returning item: {}", item.title);
+        groups.push(render_new_item(EMOJI_BACK, item));
+    }
+
+    for (old_item, new_item) in &diff.updated_items {
+        info!("Sending notification for updated item: {}", new_item.title);
+        groups.push(render_updated_item(old_item, new_item));
+    }
+
+    for item in &diff.deleted_items {
+        info!("Sending notification for deleted item: {}", item.title);
+        groups.push(render_deleted_item(item));
+    }
+
+    let mut chunks = chunk_blocks(groups);
+
+    // only ping once, at the very end of the update
+    let ping = render_channel_ping();
+    match chunks.last_mut() {
+        Some(chunk) if chunk.len() + ping.len() <= MAX_BLOCKS_PER_MESSAGE => chunk.extend(ping),
+        _ => chunks.push(ping),
+    }
+
+    let summary = diff.summary();
+    let total = chunks.len();
+
+    for (i, blocks) in chunks.into_iter().enumerate().skip(*sent) {
+        let text = if total > 1 {
+            format!("{summary} ({}/{total})", i + 1)
+        } else {
+            summary.clone()
+        };
+        let payload = SlackMessageContent::new()
+            .with_text(text)
+            .with_blocks(blocks);
+
+        CLIENT
+            .post(webhook_url.clone())
+            .json(&payload)
+            .send()?
+            .error_for_status()?;
+        *sent = i + 1;
+    }
+
+    info!("Successfully sent {total} Slack message(s)");
+    Ok(())
+}
@@ -1,49 +1,77 @@
 use std::fs::{self, File};
-use std::path::Path;
 
-use crate::config::CONFIG;
-use crate::scraper::{CLIENT, ShopItems};
+use crate::archive::{ArchivedImage, archive_image, archived_image};
+use crate::config::{CONFIG, MirrorKind};
+use crate::metrics::{CDN_CACHE_HITS, CDN_UPLOADS, LAST_SNAPSHOT_WRITE};
+use crate::mirror::{ImageMirror, build_mirror};
+use crate::scraper::ShopItems;
 
-use color_eyre::{Result, eyre::eyre};
+use color_eyre::Result;
 use dashmap::DashMap;
 use log::debug;
-use once_cell::sync::Lazy;
-use reqwest::{
-    Url,
-    blocking::multipart::{Form, Part},
-};
-use serde::Deserialize;
-use sled::{Config, Db};
+use once_cell::sync::{Lazy, OnceCell};
+use reqwest::Url;
+use sled::{Config, Db, Tree};
 use std::sync::Arc;
 
 const LATEST_SNAPSHOT_POINTER_PATH: &str = "latest-snapshot.ptr";
 const CDN_CACHE_PATH: &str = "cdn-cache.sled";
 
+const SNAPSHOT_PREFIX: &str = "snap_";
+const SNAPSHOT_SUFFIX: &str = ".json";
+
+pub fn load_snapshot(name: &str) -> Result<ShopItems> {
+    Ok(serde_json::from_reader(File::open(
+        CONFIG.storage_path.join(name),
+    )?)?)
+}
+
 pub fn load_latest_snapshot() -> Result<Option<ShopItems>> {
     match std::fs::read_to_string(CONFIG.storage_path.join(LATEST_SNAPSHOT_POINTER_PATH)) {
-        Ok(snap_ptr) => Ok(Some(serde_json::from_reader(File::open(
-            CONFIG.storage_path.join(snap_ptr),
-        )?)?)),
+        Ok(snap_ptr) => Ok(Some(load_snapshot(&snap_ptr)?)),
         Err(_) => Ok(None),
     }
 }
 
-pub fn write_new_snapshot(items: ShopItems) -> Result<()> {
+// oldest first - the timestamp format sorts lexicographically
+pub fn list_snapshots() -> Result<Vec<String>> {
+    let mut names = Vec::new();
+    for entry in fs::read_dir(&CONFIG.storage_path)? {
+        let name = entry?.file_name().to_string_lossy().into_owned();
+        if name.starts_with(SNAPSHOT_PREFIX) && name.ends_with(SNAPSHOT_SUFFIX) {
+            names.push(name);
+        }
+    }
+    names.sort();
+    Ok(names)
+}
+
+// `snap_2025-01-02-03:04:05.json` -> `2025-01-02T03:04:05Z`
+pub fn snapshot_time(name: &str) -> Option<String> {
+    let ts = name
+        .strip_prefix(SNAPSHOT_PREFIX)?
+        .strip_suffix(SNAPSHOT_SUFFIX)?;
+    let (date, time) = ts.split_at_checked(10)?;
+    Some(format!("{date}T{}Z", time.strip_prefix('-')?))
+}
+
+pub fn write_new_snapshot(items: &ShopItems) -> Result<String> {
     let ts = time_format::now().unwrap();
     let snap_path = format!(
-        "snap_{}.json",
+        "{SNAPSHOT_PREFIX}{}{SNAPSHOT_SUFFIX}",
         time_format::strftime_utc("%Y-%m-%d-%H:%M:%S", ts).unwrap()
     );
     fs::create_dir_all(&CONFIG.storage_path)?;
     fs::write(
         CONFIG.storage_path.join(&snap_path),
-        serde_json::to_string_pretty(&items)?,
+        serde_json::to_string_pretty(items)?,
     )?;
     fs::write(
         CONFIG.storage_path.join(LATEST_SNAPSHOT_POINTER_PATH),
-        snap_path,
+        &snap_path,
     )?;
-    Ok(())
+    LAST_SNAPSHOT_WRITE.set(ts);
+    Ok(snap_path)
 }
 
 pub static CDN_CACHE_DB: Lazy<Db> = Lazy::new(|| {
@@ -54,56 +82,121 @@ pub static CDN_CACHE_DB: Lazy<Db> = Lazy::new(|| {
         .unwrap()
 });
 
-static UPLOAD_ONCE: Lazy<DashMap<usize, Arc<once_cell::sync::OnceCell<Url>>>> =
-    Lazy::new(DashMap::new);
+static IMAGE_MIRROR: OnceCell<Option<Box<dyn ImageMirror>>> = OnceCell::new();
 
-#[derive(Deserialize)]
-struct CdnResponse {
-    url: Url,
+// the mirror everything gets uploaded to, or `None` to leave images on flavortown - `main`
+// calls this before anything runs, so a bad mirror config stops the tracker at startup rather
+// than failing every upload
+pub fn image_mirror() -> Result<Option<&'static dyn ImageMirror>> {
+    Ok(IMAGE_MIRROR
+        .get_or_try_init(|| build_mirror(&CONFIG))?
+        .as_deref())
 }
 
-pub fn upload_to_cdn(image_id: usize, image_url: &Url) -> Result<Url> {
-    let key = image_id.to_le_bytes();
+// sha256 -> mirrored url, per mirror kind so switching mirrors re-uploads everything
+pub static MIRROR_URLS: Lazy<Tree> = Lazy::new(|| {
+    CDN_CACHE_DB
+        .open_tree(format!("mirror-urls-{}", CONFIG.image_mirror.as_str()))
+        .unwrap()
+});
+
+// single-flight cells, so concurrent scrapes of the same image download it once...
+static UPLOAD_ONCE: Lazy<DashMap<usize, Arc<OnceCell<Url>>>> = Lazy::new(DashMap::new);
+// ...and items that share a picture under different blobs upload it once
+static UPLOAD_ONCE_BY_HASH: Lazy<DashMap<String, Arc<OnceCell<Url>>>> = Lazy::new(DashMap::new);
+
+fn once_cell<K: Eq + std::hash::Hash>(
+    cells: &DashMap<K, Arc<OnceCell<Url>>>,
+    key: K,
+) -> Arc<OnceCell<Url>> {
+    cells
+        .entry(key)
+        .or_insert_with(|| Arc::new(OnceCell::new()))
+        .clone()
+}
 
-    if let Some(cached) = CDN_CACHE_DB.get(key)? {
-        let url_str = std::str::from_utf8(&cached)?;
-        return Ok(Url::parse(url_str)?);
+// before images were archived, the hack club cdn cache mapped image_id -> url in the default tree
+fn legacy_cdn_url(image_id: usize) -> Result<Option<Url>> {
+    if CONFIG.image_mirror != MirrorKind::HackClub {
+        return Ok(None);
     }
+    match CDN_CACHE_DB.get(image_id.to_le_bytes())? {
+        Some(url) => Ok(Some(Url::parse(std::str::from_utf8(&url)?)?)),
+        None => Ok(None),
+    }
+}
 
-    // get the cell/lock for this specific image_id.
-    debug!("Didn't find {image_url} (blob ID: {image_id}) - uploading to CDN.");
-    let cell = UPLOAD_ONCE
-        .entry(image_id)
-        .or_insert_with(|| Arc::new(once_cell::sync::OnceCell::new()))
-        .clone();
-
-    // only runs once per image_id.
-    let file = CLIENT.get(image_url.clone()).send()?.bytes()?.to_vec();
-    let ext = ext_from_url(image_url).ok_or_else(|| {
-        eyre!("when trying to upload {image_url}, I couldn't get the file extension")
-    })?;
-    let form = Form::new().part("file", Part::bytes(file).file_name(format!("image.{ext}")));
+pub fn forget_legacy_cdn_url(image_id: usize) -> Result<()> {
+    CDN_CACHE_DB.remove(image_id.to_le_bytes())?;
+    Ok(())
+}
+
+fn mirrored_url(sha256: &str) -> Result<Option<Url>> {
+    match MIRROR_URLS.get(sha256)? {
+        Some(cached) => {
+            CDN_CACHE_HITS.inc();
+            Ok(Some(Url::parse(std::str::from_utf8(&cached)?)?))
+        }
+        None => Ok(None),
+    }
+}
 
+fn mirror_image(
+    mirror: &dyn ImageMirror,
+    image_id: usize,
+    archived: &ArchivedImage,
+) -> Result<Url> {
+    if let Some(url) = mirrored_url(&archived.sha256)? {
+        return Ok(url);
+    }
+
+    // get the cell/lock for this image's content, shared by every blob with the same picture.
+    let cell = once_cell(&UPLOAD_ONCE_BY_HASH, archived.sha256.clone());
+
+    // only runs once per image hash.
     let cdn_url = cell.get_or_try_init(|| {
-        let json: CdnResponse = CLIENT
-            .post("https://cdn.hackclub.com/api/file")
-            .multipart(form)
-            .bearer_auth("beans")
-            .send()?
-            .error_for_status()?
-            .json()?;
-        CDN_CACHE_DB.insert(key, json.url.as_str().as_bytes())?;
-        Ok::<Url, color_eyre::eyre::ErrReport>(json.url)
+        let url = match legacy_cdn_url(image_id)? {
+            Some(url) => url,
+            None => {
+                debug!(
+                    "Didn't find {} (blob ID: {image_id}) - uploading to the {} mirror.",
+                    archived.file_name(),
+                    mirror.kind()
+                );
+                let url = mirror.upload(&archived.file_name(), archived.read()?)?;
+                CDN_UPLOADS.inc();
+                url
+            }
+        };
+        MIRROR_URLS.insert(&archived.sha256, url.as_str().as_bytes())?;
+        Ok::<Url, color_eyre::eyre::ErrReport>(url)
     })?;
-
     Ok(cdn_url.clone())
 }
 
-fn ext_from_url(url: &Url) -> Option<String> {
-    let filename = url.path_segments()?.next_back()?;
+pub fn upload_to_cdn(image_id: usize, image_url: &Url) -> Result<Url> {
+    // already archived, so there's nothing to download - only the upload needs deduplicating
+    if let Some(archived) = archived_image(image_id)? {
+        return match image_mirror()? {
+            Some(mirror) => mirror_image(mirror, image_id, &archived),
+            None => Ok(image_url.clone()),
+        };
+    }
+
+    // get the cell/lock for this specific image_id.
+    let cell = once_cell(&UPLOAD_ONCE, image_id);
 
-    Path::new(filename)
-        .extension()
-        .and_then(|e| e.to_str())
-        .map(|s| s.to_string())
+    // only runs once per image_id - the download and extension detection included.
+    let cdn_url = cell.get_or_try_init(|| {
+        let archived = match archived_image(image_id)? {
+            Some(archived) => archived,
+            None => archive_image(image_id, image_url)?,
+        };
+        match image_mirror()? {
+            Some(mirror) => mirror_image(mirror, image_id, &archived),
+            None => Ok(image_url.clone()),
+        }
+    })?;
+
+    Ok(cdn_url.clone())
 }